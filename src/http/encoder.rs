@@ -0,0 +1,250 @@
+//! HTTP message encoder: the write side of `bee`, complementing `parser`.
+//!
+//! Where `parser::Parser` turns bytes into events, these functions turn
+//! request/response parts back into bytes, so one crate can handle both
+//! directions of an HTTP/1.x connection. There's no shared mutable state
+//! between request lines, status lines and header fields, so they're
+//! plain functions that append to a caller-owned buffer rather than a
+//! struct with a handler trait; only chunked body framing needs to track
+//! anything between calls, which is what `ChunkEncoder` is for.
+
+#![experimental]
+
+use std::ascii::AsciiExt;
+
+use http;
+use http::parser::{CR, LF, SPACE, COLON, ZERO, LOWER_A};
+
+/// Write a request line: `METHOD SP target SP version CRLF`. `target` is
+/// written as-is, so callers that already percent-encoded their request
+/// target don't get it mangled.
+pub fn encode_request_line(out: &mut Vec<u8>, method: http::HttpMethod, target: &[u8], version: http::HttpVersion) {
+    out.push_all(format!("{}", method).as_bytes());
+    out.push(SPACE);
+    out.push_all(target);
+    out.push(SPACE);
+    out.push_all(format!("{}", version).as_bytes());
+    out.push(CR);
+    out.push(LF);
+}
+
+/// Write a status line: `version SP status SP reason CRLF`.
+pub fn encode_status_line(out: &mut Vec<u8>, version: http::HttpVersion, status: uint, reason: &str) {
+    out.push_all(format!("{}", version).as_bytes());
+    out.push(SPACE);
+    out.push_all(format!("{}", status).as_bytes());
+    out.push(SPACE);
+    out.push_all(reason.as_bytes());
+    out.push(CR);
+    out.push(LF);
+}
+
+/// Write a single header field: `name: value CRLF`.
+pub fn encode_header(out: &mut Vec<u8>, name: &str, value: &str) {
+    out.push_all(name.as_bytes());
+    out.push(COLON);
+    out.push(SPACE);
+    out.push_all(value.as_bytes());
+    out.push(CR);
+    out.push(LF);
+}
+
+/// Write the blank line that ends a header block.
+pub fn end_headers(out: &mut Vec<u8>) {
+    out.push(CR);
+    out.push(LF);
+}
+
+/// Streaming encoder for `Transfer-Encoding: chunked` bodies. Each call to
+/// `write` frames one buffer as its own chunk, so a server can produce a
+/// response as its body becomes available instead of buffering all of it
+/// up front. `close` appends the terminating zero-size chunk.
+pub struct ChunkEncoder {
+    closed: bool,
+}
+
+impl ChunkEncoder {
+    /// Create a new encoder positioned at the start of a chunked body.
+    pub fn new() -> ChunkEncoder {
+        ChunkEncoder { closed: false }
+    }
+
+    /// Frame `data` as one chunk and append it to `out`. Writing an empty
+    /// buffer is a no-op rather than emitting a (premature) zero-size
+    /// chunk; call `close` to end the body.
+    pub fn write(&mut self, out: &mut Vec<u8>, data: &[u8]) {
+        if data.is_empty() { return }
+        encode_chunk_size(out, data.len());
+        out.push(CR);
+        out.push(LF);
+        out.push_all(data);
+        out.push(CR);
+        out.push(LF);
+    }
+
+    /// Append the terminating `0 CRLF CRLF` that ends a chunked body.
+    /// Idempotent: only the first call writes anything, so callers don't
+    /// need to track whether they've already closed the body themselves.
+    pub fn close(&mut self, out: &mut Vec<u8>) {
+        if self.closed { return }
+        self.closed = true;
+        out.push(ZERO);
+        out.push(CR);
+        out.push(LF);
+        out.push(CR);
+        out.push(LF);
+    }
+}
+
+/// Write `size` as lowercase hex digits, with no leading zeros.
+fn encode_chunk_size(out: &mut Vec<u8>, size: uint) {
+    let mut digits: Vec<u8> = Vec::new();
+    let mut rest = size;
+    loop {
+        let d = (rest % 16) as u8;
+        digits.push(if d < 10 { ZERO + d } else { LOWER_A + (d - 10) });
+        rest /= 16;
+        if rest == 0 { break }
+    }
+    let mut i = digits.len();
+    while i > 0 {
+        i -= 1;
+        out.push(digits[i]);
+    }
+}
+
+/// Status codes whose response must never carry a body or
+/// `Content-Length`: 1xx informational, `204 No Content`, `304 Not
+/// Modified`.
+fn forbids_body(status: uint) -> bool {
+    status / 100 == 1 || status == 204 || status == 304
+}
+
+/// The standard reason phrase for the status codes a handler is likely
+/// to send, falling back to `"Unknown"` for anything else; `Response`
+/// only uses this as a default, and `Response::set_reason` can always
+/// override it.
+fn status_reason(status: uint) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        102 => "Processing",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        417 => "Expectation Failed",
+        426 => "Upgrade Required",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _   => "Unknown",
+    }
+}
+
+/// An HTTP response being assembled before it's written to a connection:
+/// status line, headers and an optional body, with `Content-Length` and
+/// `Connection` computed by `write` rather than hand-written by the
+/// caller. Mirrors the vocabulary `parser` already defines
+/// (`http::HttpVersion`) so a handler that parsed a request with `Parser`
+/// can answer it with the same types.
+pub struct Response {
+    version: http::HttpVersion,
+    status: uint,
+    reason: String,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+    keep_alive: bool,
+}
+
+impl Response {
+    /// Start a response with `version` and `status`, defaulting the
+    /// reason phrase from `status` and the connection to keep-alive.
+    pub fn new(version: http::HttpVersion, status: uint) -> Response {
+        Response {
+            version: version,
+            status: status,
+            reason: status_reason(status).to_string(),
+            headers: Vec::new(),
+            body: None,
+            keep_alive: true,
+        }
+    }
+
+    /// Override the reason phrase `write` would otherwise default from
+    /// the status code.
+    pub fn set_reason(&mut self, reason: &str) -> &mut Response {
+        self.reason = reason.to_string();
+        self
+    }
+
+    /// Append a header field. `Content-Length` and `Connection` are
+    /// computed by `write`; setting them here has no effect, since
+    /// fields of either name (matched case-insensitively) are dropped.
+    pub fn header(&mut self, name: &str, value: &str) -> &mut Response {
+        if !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("Connection") {
+            self.headers.push((name.to_string(), value.to_string()));
+        }
+        self
+    }
+
+    /// Set the response body. `write` omits it for status codes that
+    /// must not carry one (1xx, 204, 304), regardless of this call.
+    pub fn set_body(&mut self, body: Vec<u8>) -> &mut Response {
+        self.body = Some(body);
+        self
+    }
+
+    /// Whether `write` should advertise `Connection: keep-alive` (the
+    /// default) or `Connection: close`.
+    pub fn set_keep_alive(&mut self, keep_alive: bool) -> &mut Response {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Render the status line, the caller's headers plus an automatic
+    /// `Connection` and (where the status allows a body) `Content-Length`,
+    /// and the body itself, appending it all to `out`.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        encode_status_line(out, self.version, self.status, self.reason.as_slice());
+        for &(ref name, ref value) in self.headers.iter() {
+            encode_header(out, name.as_slice(), value.as_slice());
+        }
+        encode_header(out, "Connection", if self.keep_alive { "keep-alive" } else { "close" });
+        let send_body = !forbids_body(self.status);
+        if send_body {
+            let len = match self.body {
+                Some(ref b) => b.len(),
+                None => 0,
+            };
+            encode_header(out, "Content-Length", format!("{}", len).as_slice());
+        }
+        end_headers(out);
+        if send_body {
+            match self.body {
+                Some(ref b) => out.push_all(b.as_slice()),
+                None => (),
+            }
+        }
+    }
+}
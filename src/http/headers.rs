@@ -0,0 +1,89 @@
+//! A case-insensitive, order-preserving, multi-valued header collection.
+//!
+//! `parser::Parser` hands raw header bytes to `MessageHandler` as they're
+//! decoded rather than building one of these itself, but a handler that
+//! wants `Connection`/`Transfer-Encoding`-style lookups without
+//! re-implementing case folding and comma-token splitting can collect
+//! into a `Headers` and use its typed accessors instead — the same rules
+//! `Parser` already applies internally to decide keep-alive and chunked
+//! framing.
+
+#![experimental]
+
+use std::ascii::AsciiExt;
+
+/// An HTTP header collection, keyed case-insensitively on field name
+/// while preserving insertion order and every value given a name, so
+/// headers that are legitimately repeated (e.g. `Set-Cookie`) or
+/// comma-folded (e.g. `Accept`) aren't silently collapsed to one.
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Create an empty header collection.
+    pub fn new() -> Headers {
+        Headers { entries: Vec::new() }
+    }
+
+    /// Append a field as given. Never overwrites an existing field of the
+    /// same name; `get` returns the first match, `get_all` returns all of
+    /// them in the order they were pushed.
+    pub fn push(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    /// The number of fields stored, counting repeated names separately.
+    pub fn len(&self) -> uint {
+        self.entries.len()
+    }
+
+    /// The first value stored under `name`, matched case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        for &(ref n, ref v) in self.entries.iter() {
+            if n.as_slice().eq_ignore_ascii_case(name) {
+                return Some(v.as_slice());
+            }
+        }
+        None
+    }
+
+    /// Every value stored under `name`, matched case-insensitively, in
+    /// the order they were pushed.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries.iter()
+            .filter(|&&(ref n, _)| n.as_slice().eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_slice())
+            .collect()
+    }
+
+    /// The parsed `Content-Length` value, or `None` if the header is
+    /// absent or not a valid unsigned integer.
+    pub fn content_length(&self) -> Option<uint> {
+        self.get("Content-Length").and_then(|v| from_str(v.trim()))
+    }
+
+    /// Whether `Transfer-Encoding`'s last comma-separated token is
+    /// `chunked`, matched case-insensitively — the same rule `Parser`
+    /// applies to switch into chunked body decoding, so a handler that
+    /// collected its own `Headers` agrees with the parser it came from.
+    pub fn is_chunked(&self) -> bool {
+        match self.get("Transfer-Encoding") {
+            Some(v) => match v.split(',').last() {
+                Some(tok) => tok.trim().eq_ignore_ascii_case("chunked"),
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    /// The `Connection` header's comma-separated tokens, trimmed and
+    /// lower-cased, in order (e.g. `["keep-alive"]`, `["upgrade"]`).
+    /// Empty if the header is absent.
+    pub fn connection_tokens(&self) -> Vec<String> {
+        match self.get("Connection") {
+            Some(v) => v.split(',').map(|t| t.trim().to_ascii_lower()).collect(),
+            None => Vec::new(),
+        }
+    }
+}
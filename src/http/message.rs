@@ -0,0 +1,178 @@
+//! A ready-made `MessageHandler` that materializes a structured `Message`,
+//! for callers that just want a parsed request/response rather than a
+//! stream of `push`/length callbacks to reassemble themselves.
+//!
+//! `parser::Parser` itself stays SAX-style, so
+//! this is additive: `CollectingHandler` implements `MessageHandler` the
+//! same way `lib.rs`'s usage example does by hand, buffering each
+//! `write`-delivered slice and slicing it back apart on the matching
+//! length callback, then handing the result to the caller once
+//! `on_message_complete` fires.
+
+#![experimental]
+
+use std::mem;
+use std::str::from_utf8;
+
+use http;
+use http::headers::Headers;
+use http::parser::{MessageHandler, Parser};
+
+/// A fully parsed HTTP message: either a request (`method`/`url` set,
+/// `status_code` `None`) or a response (`status_code` set, `method`/`url`
+/// `None`), depending on which `Parser` produced it.
+pub struct Message {
+    /// The parsed HTTP version, once the version line has been seen.
+    pub version: Option<http::HttpVersion>,
+    /// The request method, for messages parsed with `ParseRequest`.
+    pub method: Option<http::HttpMethod>,
+    /// The raw request target, for messages parsed with `ParseRequest`.
+    pub url: Option<String>,
+    /// The status code, for messages parsed with `ParseResponse`.
+    pub status_code: Option<uint>,
+    /// Every header field, in the order they were sent. Repeated fields
+    /// are preserved rather than collapsed, and `get`/`get_all` match
+    /// names case-insensitively; see `headers::Headers`.
+    pub headers: Headers,
+    /// The message body, or empty if the message carried none.
+    pub body: Vec<u8>,
+}
+
+impl Message {
+    fn new() -> Message {
+        Message {
+            version: None,
+            method: None,
+            url: None,
+            status_code: None,
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+}
+
+/// A `MessageHandler` that accumulates a single `Message` from the raw
+/// `write`/length callbacks and reports it complete via `is_finished`.
+/// Intended for one message at a time: create a fresh `CollectingHandler`
+/// per `Parser::parse`/`parse_all` call (or between `take_message` calls)
+/// rather than reusing one across a pipelined connection, since `write`
+/// accumulates into a single buffer that's cleared as each field is cut
+/// off of it.
+pub struct CollectingHandler {
+    message: Message,
+    buffer: Vec<u8>,
+    header_name: Option<String>,
+    finished: bool,
+}
+
+impl CollectingHandler {
+    /// Create a handler with no message collected yet.
+    pub fn new() -> CollectingHandler {
+        CollectingHandler {
+            message: Message::new(),
+            buffer: Vec::new(),
+            header_name: None,
+            finished: false,
+        }
+    }
+
+    /// Whether `on_message_complete` has fired since this handler was
+    /// created or last reset via `take_message`.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Take the collected `Message` and reset this handler to collect a
+    /// fresh one, so it can be fed to `Parser::parse` again for the next
+    /// pipelined message.
+    pub fn take_message(&mut self) -> Message {
+        self.finished = false;
+        self.header_name = None;
+        self.buffer.clear();
+        mem::replace(&mut self.message, Message::new())
+    }
+
+    #[inline]
+    fn take_buffer(&mut self, length: uint) -> String {
+        let len = self.buffer.len();
+        let slice = self.buffer.slice_from(len - length);
+        let s = match from_utf8(slice) {
+            Some(s) => s.to_string(),
+            None => String::new(),
+        };
+        self.buffer.truncate(len - length);
+        s
+    }
+}
+
+impl MessageHandler for CollectingHandler {
+    fn on_method(&mut self, _: &Parser, method: http::HttpMethod) {
+        self.message.method = Some(method);
+    }
+
+    fn on_url(&mut self, _: &Parser, length: uint) {
+        self.message.url = Some(self.take_buffer(length));
+    }
+
+    fn on_version(&mut self, _: &Parser, version: http::HttpVersion) {
+        self.message.version = Some(version);
+    }
+
+    fn on_status(&mut self, _: &Parser, status: uint) {
+        self.message.status_code = Some(status);
+    }
+
+    fn on_header_field(&mut self, _: &Parser, length: uint) {
+        self.header_name = Some(self.take_buffer(length));
+    }
+
+    fn on_header_value(&mut self, _: &Parser, length: uint) {
+        let value = self.take_buffer(length);
+        match self.header_name.take() {
+            Some(name) => self.message.headers.push(name.as_slice(), value.as_slice()),
+            None => (),
+        }
+    }
+
+    fn on_body(&mut self, _: &Parser, length: uint) {
+        // Unlike `url`/headers, the body is arbitrary bytes rather than
+        // text, so it's sliced out directly instead of through
+        // `take_buffer`'s UTF-8 decode.
+        let len = self.buffer.len();
+        self.message.body.push_all(self.buffer.slice_from(len - length));
+        self.buffer.truncate(len - length);
+    }
+
+    fn on_trailer(&mut self, _: &Parser, length: uint) {
+        // Trailers arrive as a single `Name: Value` line, unlike the
+        // header block's separate field/value callbacks, so split it
+        // ourselves before folding it into the same `Headers` as any
+        // regular header: RFC 7230 treats a chunked trailer as part of
+        // the header set, just delivered late.
+        let line = self.take_buffer(length);
+        match line.as_slice().find(':') {
+            Some(colon) => {
+                let name = line.as_slice().slice_to(colon).trim();
+                let value = line.as_slice().slice_from(colon + 1).trim();
+                self.message.headers.push(name, value);
+            }
+            None => (),
+        }
+    }
+
+    fn on_message_complete(&mut self, _: &Parser) {
+        // A chunked body never reaches `on_body` (each chunk only gets a
+        // `write` call), so whatever's left in the buffer once the
+        // message is done is exactly that body, already stripped of the
+        // trailer lines consumed above.
+        if !self.buffer.is_empty() {
+            let rest = mem::replace(&mut self.buffer, Vec::new());
+            self.message.body.push_all(rest.as_slice());
+        }
+        self.finished = true;
+    }
+
+    fn write(&mut self, _: &Parser, bytes: &[u8]) {
+        self.buffer.push_all(bytes);
+    }
+}
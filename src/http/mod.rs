@@ -123,5 +123,19 @@ impl Show for HttpMethod {
     }
 }
 
+/// A protocol a connection can be switched to once the parser recognizes it.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum Protocol {
+    /// HTTP/2, negotiated via the prior-knowledge client connection preface.
+    H2,
+    /// A `CONNECT` tunnel, or a `Connection: upgrade` switch (e.g.
+    /// WebSocket) named by the request's `Upgrade:` header.
+    Tunnel,
+}
+
 pub mod parser;
+pub mod encoder;
+pub mod headers;
+pub mod message;
+pub mod websocket;
 #[cfg(test)] pub mod tests;
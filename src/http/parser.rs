@@ -3,6 +3,8 @@
 #![experimental]
 
 use UINT_MAX = std::uint::MAX;
+use std::io::IoResult;
+use std::str::from_utf8;
 
 use http;
 
@@ -13,6 +15,35 @@ pub enum ParseType {
     ParseRequest,
     /// Parse response only.
     ParseResponse,
+    /// Parse either, sniffing the first bytes of each message to tell
+    /// them apart: a line starting with the literal `HTTP/` is a
+    /// response, anything else is a request. The only request method
+    /// spelled the same way up to that point, `HEAD`, diverges at the
+    /// second byte (`E` vs `T`), so two bytes of lookahead are always
+    /// enough. Once a message has been classified, `Parser` behaves
+    /// exactly as if it had been constructed with the matching
+    /// `ParseRequest`/`ParseResponse`, including for any further
+    /// pipelined messages on the same connection.
+    ParseEither,
+}
+
+#[deriving(PartialEq, Eq, Clone, Show)]
+/// A component of a request target decomposed by `Parser::set_url_decode`.
+pub enum UrlComponent {
+    /// The scheme of an absolute-form target, e.g. `http`.
+    Scheme,
+    /// The host of an absolute-form target, or of a CONNECT authority-form
+    /// target.
+    Host,
+    /// The port of an absolute-form target, or of a CONNECT authority-form
+    /// target.
+    Port,
+    /// The request path, with `%XX` escapes decoded.
+    Path,
+    /// The raw query string, without the leading `?`.
+    Query,
+    /// The raw fragment, without the leading `#`.
+    Fragment,
 }
 
 /// Parser event handler.
@@ -67,18 +98,89 @@ pub trait MessageHandler {
         return false;
     }
 
+    #[allow(unused_variable)]
+    /// Called once headers are complete when the request carries
+    /// `Expect: 100-continue`, before any body byte is dispatched.
+    /// Returning `true` tells the parser to go on and accept the body
+    /// (the handler is expected to have sent an interim `100 Continue`
+    /// itself); returning `false` rejects it, and the parser behaves as
+    /// though the message had no body at all. The `Expect` field name and
+    /// its `100-continue` value are matched byte-by-byte case-insensitively
+    /// (`Expect`, `expect`, `EXPECT`, ... all recognized), since clients are
+    /// inconsistent about casing here.
+    /// Default implementation accepts the body.
+    fn on_expect_continue(&mut self, parser: &Parser) -> bool {
+        true
+    }
+
     #[allow(unused_variable)]
     /// Called when body parsed.
     /// Default implementation is nothing to do.
     fn on_body(&mut self, parser: &Parser, length: uint) {
     }
 
+    #[allow(unused_variable)]
+    /// Called with each contiguous run of decoded body bytes as the parser
+    /// produces them, instead of requiring the handler to accumulate a full
+    /// copy of the body itself. Only invoked when streaming mode is enabled
+    /// via `Parser::set_streaming`. Returning an `Err` aborts parsing so a
+    /// handler can signal backpressure or give up early.
+    /// Default implementation is nothing to do.
+    fn on_body_data(&mut self, parser: &Parser, data: &[u8]) -> IoResult<()> {
+        Ok(())
+    }
+
+    #[allow(unused_variable)]
+    /// Called once per trailer line following the last chunk of a chunked
+    /// body (as advertised by a `Trailer:` header). Not called at all when
+    /// the chunked body carries no trailers. Trailers run through their own
+    /// states (`TrailerStart`/`Trailer`), separate from the request/response
+    /// header states, so `on_headers_complete` is not re-fired and no body
+    /// framing decision is repeated once they start.
+    /// Default implementation is nothing to do.
+    fn on_trailer(&mut self, parser: &Parser, length: uint) {
+    }
+
+    #[allow(unused_variable)]
+    /// Called for `Transfer-Encoding: chunked` bodies when a chunk's size
+    /// line has been decoded, before any of that chunk's payload bytes are
+    /// dispatched. `size` is the decoded chunk length, excluding the
+    /// trailing CRLF.
+    /// Default implementation is nothing to do.
+    fn on_chunk_header(&mut self, parser: &Parser, size: uint) {
+    }
+
+    #[allow(unused_variable)]
+    /// Called for `Transfer-Encoding: chunked` bodies once a chunk's
+    /// payload and trailing CRLF have both been consumed.
+    /// Default implementation is nothing to do.
+    fn on_chunk_complete(&mut self, parser: &Parser) {
+    }
+
     #[allow(unused_variable)]
     /// Called when completed to parsing of whole message.
     /// Default implementation is nothing to do.
     fn on_message_complete(&mut self, parser: &Parser) {
     }
 
+    #[allow(unused_variable)]
+    /// Called when the connection is switched to another protocol that the
+    /// parser recognized directly, e.g. the HTTP/2 client preface.
+    /// Default implementation is nothing to do.
+    fn on_upgrade(&mut self, parser: &Parser, protocol: http::Protocol) {
+    }
+
+    #[allow(unused_variable)]
+    /// Called once per decomposed piece of the request target when
+    /// `Parser::set_url_decode` is enabled, after the full target has been
+    /// buffered. Not called at all otherwise (the raw target is still
+    /// reported to `on_url` in both cases). `bytes` holds that component's
+    /// value, with `UrlComponent::Path` percent-decoded; the others are
+    /// passed through as written on the wire.
+    /// Default implementation is nothing to do.
+    fn on_url_component(&mut self, parser: &Parser, kind: UrlComponent, bytes: &[u8]) {
+    }
+
     /// Write partial data to buffer, e.g. URL, header field, message body.
     fn write(&mut self, &Parser, &[u8]);
 }
@@ -92,6 +194,8 @@ pub enum ParseError {
     InvalidMethod,
     /// Invalid URL.
     InvalidUrl,
+    /// A request's URL exceeded `ParserLimits::max_url_length`.
+    UrlTooLong,
     /// Invalid HTTP version.
     InvalidVersion,
     /// Invalid request line.
@@ -106,12 +210,165 @@ pub enum ParseError {
     InvalidHeaders,
     /// Invalid chunk data.
     InvalidChunk,
+    /// A chunk-size line contained a non-hex-digit byte.
+    InvalidChunkSize,
+    /// A `Content-Length` value overflowed `uint` while being accumulated.
+    InvalidContentLength,
+    /// A message carried both a `Content-Length` header and
+    /// `Transfer-Encoding: chunked`. Accepting both leaves the body's
+    /// framing ambiguous between this parser and any intermediary that
+    /// picks the other one, the classic HTTP request-smuggling vector, so
+    /// the message is rejected outright instead of guessing which wins.
+    AmbiguousMessageLength,
+    /// A `%XX` escape in the request target was malformed.
+    InvalidUrlEscape,
     /// Expected data, but reached EOF.
     InvalidEOFState,
+    /// Too many header fields in a single message.
+    TooManyHeaders,
+    /// A single header, or the whole header block, exceeded its size limit.
+    HeaderOverflow,
+    /// `MessageHandler::on_body_data` returned an error, aborting the parse.
+    AbortedByHandler,
 }
 
 pub type ParseResult = Result<uint, ParseError>;
 
+/// The outcome of a `Parser::parse_all` call.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct ParseAllResult {
+    /// Bytes of the input buffer consumed. Feed the remainder
+    /// (`buf.slice_from(consumed)`) to the next `parse_all` call to pick up
+    /// where this one left off, instead of copying it into a scratch buffer.
+    pub consumed: uint,
+    /// Complete messages handed to the handler during this call.
+    pub messages: uint,
+    /// `true` if the buffer ran out in the middle of a message, meaning the
+    /// caller should read more bytes before calling `parse_all` again.
+    /// `false` at a clean message boundary, including when `max_messages`
+    /// was reached with another message's bytes still unconsumed.
+    pub needs_more: bool,
+}
+
+/// Forwards every `MessageHandler` callback to `inner` unchanged, counting
+/// `on_message_complete` calls along the way. Used by `Parser::parse_all`
+/// to learn how many messages a single `parse` call produced without
+/// requiring `MessageHandler` itself to report it.
+struct CountingHandler<'a, C: 'a> {
+    inner: &'a mut C,
+    messages: uint,
+}
+
+impl<'a, C: MessageHandler> MessageHandler for CountingHandler<'a, C> {
+    fn on_message_begin(&mut self, parser: &Parser) {
+        self.inner.on_message_begin(parser)
+    }
+
+    fn on_method(&mut self, parser: &Parser, method: http::HttpMethod) {
+        self.inner.on_method(parser, method)
+    }
+
+    fn on_url(&mut self, parser: &Parser, length: uint) {
+        self.inner.on_url(parser, length)
+    }
+
+    fn on_version(&mut self, parser: &Parser, version: http::HttpVersion) {
+        self.inner.on_version(parser, version)
+    }
+
+    fn on_status(&mut self, parser: &Parser, status: uint) {
+        self.inner.on_status(parser, status)
+    }
+
+    fn on_header_field(&mut self, parser: &Parser, length: uint) {
+        self.inner.on_header_field(parser, length)
+    }
+
+    fn on_header_value(&mut self, parser: &Parser, length: uint) {
+        self.inner.on_header_value(parser, length)
+    }
+
+    fn on_headers_complete(&mut self, parser: &Parser) -> bool {
+        self.inner.on_headers_complete(parser)
+    }
+
+    fn on_expect_continue(&mut self, parser: &Parser) -> bool {
+        self.inner.on_expect_continue(parser)
+    }
+
+    fn on_body(&mut self, parser: &Parser, length: uint) {
+        self.inner.on_body(parser, length)
+    }
+
+    fn on_body_data(&mut self, parser: &Parser, data: &[u8]) -> IoResult<()> {
+        self.inner.on_body_data(parser, data)
+    }
+
+    fn on_trailer(&mut self, parser: &Parser, length: uint) {
+        self.inner.on_trailer(parser, length)
+    }
+
+    fn on_chunk_header(&mut self, parser: &Parser, size: uint) {
+        self.inner.on_chunk_header(parser, size)
+    }
+
+    fn on_chunk_complete(&mut self, parser: &Parser) {
+        self.inner.on_chunk_complete(parser)
+    }
+
+    fn on_message_complete(&mut self, parser: &Parser) {
+        self.messages += 1;
+        self.inner.on_message_complete(parser)
+    }
+
+    fn on_upgrade(&mut self, parser: &Parser, protocol: http::Protocol) {
+        self.inner.on_upgrade(parser, protocol)
+    }
+
+    fn on_url_component(&mut self, parser: &Parser, kind: UrlComponent, bytes: &[u8]) {
+        self.inner.on_url_component(parser, kind, bytes)
+    }
+
+    fn write(&mut self, parser: &Parser, bytes: &[u8]) {
+        self.inner.write(parser, bytes)
+    }
+}
+
+/// Resource limits enforced while parsing headers from an untrusted stream.
+/// Each ceiling here is checked where the corresponding bytes are counted
+/// (`ReqUrl`, `HeaderField`/`HeaderValue` and their discard-whitespace
+/// variants) and crashes the parser with a distinct `ParseError` the
+/// moment it's crossed, rather than continuing to grow `handler.write`'s
+/// buffer on the strength of an unbounded peer. `Parser::with_limits` and
+/// the `set_max_*` setters let an embedder tune these per deployment, the
+/// way a production HTTP/1 decoder caps header count and buffer size.
+pub struct ParserLimits {
+    /// Maximum number of header fields accepted in a single message.
+    pub max_headers: uint,
+    /// Maximum size in bytes of a single header field name plus value.
+    pub max_header_size: uint,
+    /// Maximum cumulative size in bytes of the whole header block.
+    pub max_headers_size: uint,
+    /// Maximum size in bytes of a request's URL.
+    pub max_url_length: uint,
+    /// Maximum size in bytes of a response's reason phrase.
+    pub max_status_line_length: uint,
+}
+
+impl ParserLimits {
+    /// Default limits: 100 headers, 8 KiB per header, 128 KiB total,
+    /// 8 KiB URL, 8 KiB reason phrase.
+    pub fn new() -> ParserLimits {
+        ParserLimits {
+            max_headers: 100,
+            max_header_size: 8 * 1024,
+            max_headers_size: 128 * 1024,
+            max_url_length: 8 * 1024,
+            max_status_line_length: 8 * 1024,
+        }
+    }
+}
+
 /// HTTP parser.
 pub struct Parser {
     // parser internal state
@@ -129,8 +386,34 @@ pub struct Parser {
     // common header
     message_body_rest: uint,
     upgrade: bool,
-    keep_alive: bool,
+    has_upgrade_header: bool,
+    expect_continue: bool,
+    connection_close: bool,
+    connection_keep_alive: bool,
     chunked: bool,
+    has_content_length: bool,
+    header_kind: HeaderState,
+    token_start: uint,
+    upgrade_buffer: Vec<u8>,
+
+    // HTTP/2 detection
+    saw_h2_preface: bool,
+
+    // pipelining
+    pipeline_keep_alive: bool,
+
+    // resource limits
+    limits: ParserLimits,
+    header_count: uint,
+    header_size: uint,
+    headers_size: uint,
+
+    // body delivery
+    streaming: bool,
+
+    // request target decomposition
+    url_decode: bool,
+    url_buffer: Vec<u8>,
 
     // request
     method: Option<http::HttpMethod>,
@@ -140,14 +423,21 @@ pub struct Parser {
 }
 
 impl Parser {
-    /// Create a new `Parser`.
+    /// Create a new `Parser` with the default resource limits.
     pub fn new(t: ParseType) -> Parser {
+        Parser::with_limits(t, ParserLimits::new())
+    }
+
+    /// Create a new `Parser` with custom resource limits, for use against
+    /// untrusted streams where the defaults aren't appropriate.
+    pub fn with_limits(t: ParseType, limits: ParserLimits) -> Parser {
         Parser {
             parser_type: t,
             http_version: None,
             state: match t {
                 ParseRequest  => StartReq,
                 ParseResponse => StartRes,
+                ParseEither   => StartAny,
             },
             hstate: HeaderGeneral,
             method: None,
@@ -157,14 +447,88 @@ impl Parser {
             index: 0,
             major: 0,
             minor: 0,
-            keep_alive: false,
+            connection_close: false,
+            connection_keep_alive: false,
             upgrade: false,
+            has_upgrade_header: false,
+            expect_continue: false,
             chunked: false,
+            has_content_length: false,
+            header_kind: HeaderGeneral,
+            token_start: 0,
+            upgrade_buffer: Vec::new(),
+            saw_h2_preface: false,
+            pipeline_keep_alive: false,
+            limits: limits,
+            header_count: 0,
+            header_size: 0,
+            headers_size: 0,
+            streaming: false,
+            url_decode: false,
+            url_buffer: Vec::new(),
         }
     }
 
+    /// Override the maximum number of header fields accepted in a single
+    /// message. See `ParserLimits::max_headers`.
+    pub fn set_max_headers(&mut self, max_headers: uint) {
+        self.limits.max_headers = max_headers;
+    }
+
+    /// Override the maximum size in bytes of a single header field name
+    /// plus value. See `ParserLimits::max_header_size`.
+    pub fn set_max_header_size(&mut self, max_header_size: uint) {
+        self.limits.max_header_size = max_header_size;
+    }
+
+    /// Override the maximum cumulative size in bytes of the whole header
+    /// block. See `ParserLimits::max_headers_size`.
+    pub fn set_max_headers_size(&mut self, max_headers_size: uint) {
+        self.limits.max_headers_size = max_headers_size;
+    }
+
+    /// Override the maximum size in bytes of a request's URL. See
+    /// `ParserLimits::max_url_length`.
+    pub fn set_max_url_length(&mut self, max_url_length: uint) {
+        self.limits.max_url_length = max_url_length;
+    }
+
+    /// Override the maximum size in bytes of a response's reason phrase.
+    /// See `ParserLimits::max_status_line_length`.
+    pub fn set_max_status_line_length(&mut self, max_status_line_length: uint) {
+        self.limits.max_status_line_length = max_status_line_length;
+    }
+
+    /// Enable or disable streaming body delivery. When enabled, body bytes
+    /// are handed to `MessageHandler::on_body_data` as they are decoded
+    /// instead of via `write`/`on_body`, so the handler never has to buffer
+    /// a full copy of the body itself. Disabled by default for backward
+    /// compatibility.
+    pub fn set_streaming(&mut self, enabled: bool) {
+        self.streaming = enabled;
+    }
+
+    /// Enable or disable decomposition of the request target into
+    /// structured components reported via `MessageHandler::on_url_component`
+    /// (scheme/host/port/path/query/fragment, with `%XX` escapes in the path
+    /// decoded). Disabled by default; when disabled, only the raw target
+    /// length is reported, via `on_url`.
+    pub fn set_url_decode(&mut self, enabled: bool) {
+        self.url_decode = enabled;
+    }
+
     #[allow(unused_must_use)]
-    /// Parse HTTP message.
+    /// Feed a slice of bytes from the connection into the parser, driving
+    /// the same byte-at-a-time state machine forward and returning how
+    /// many of them were consumed. Already the resumable, zero-copy entry
+    /// point this crate is built around: nothing here reads a `Reader` or
+    /// blocks on I/O, `data` can be as large or as small as the caller's
+    /// own buffering happens to produce, and the suspended-mid-token case
+    /// (a URL, header name or value split across two calls) falls out of
+    /// `index` already tracking how far into the current token parsing
+    /// had gotten. Callers own their own socket reads and simply call
+    /// this again with the next slice, starting at `buf.slice_from(n)`
+    /// where `n` is what the previous call returned.
     pub fn parse<C: MessageHandler>(&mut self, data: &[u8], handler: &mut C) -> ParseResult {
         if self.state == Dead { return Ok(0) }
         if self.state == Crashed { return Err(OtherParseError) }
@@ -179,7 +543,67 @@ impl Parser {
              || self.state == ChunkData) {
             for &byte in data.iter() {
                 read += 1;
+                let counting_header = match self.state {
+                    HeaderFieldStart | HeaderField
+                        | HeaderValueDiscardWS | HeaderValueDiscardWSAlmostDone | HeaderValueDiscardLWS
+                        | HeaderValue | HeaderAlmostDone => true,
+                    _ => false,
+                };
                 match self.state {
+                    // `ParseEither` sniffs the first one or two bytes to
+                    // tell a request from a response apart, settling
+                    // `self.parser_type` and landing in the same states
+                    // `StartReq`/`StartRes` would have reached by this
+                    // point. This mirrors their dispatch inline (rather
+                    // than slicing back into `data` to replay it) because
+                    // those bytes may have arrived in an earlier `parse`
+                    // call whose buffer is already gone. `HEAD` is the
+                    // only method spelled the same way `HTTP/` is this
+                    // far, so a lone `H` stays ambiguous for exactly one
+                    // more byte.
+                    StartAny => {
+                        self.method = match byte {
+                            UPPER_H => { self.state = StartAnyH; None }
+                            CR | LF => break,
+                            UPPER_C => Some(http::HttpConnect),     // or CHECKOUT, COPY
+                            UPPER_D => Some(http::HttpDelete),
+                            UPPER_G => Some(http::HttpGet),
+                            UPPER_L => Some(http::HttpLink),        // or LOCK
+                            UPPER_M => Some(http::HttpMkCol),       // or M-SEARCH, MERGE, MKACTIVITY, MKCALENDER
+                            UPPER_N => Some(http::HttpNotify),
+                            UPPER_O => Some(http::HttpOptions),
+                            UPPER_P => Some(http::HttpPut),         // or PATCH, POST, PROPPATCH, PROPFIND
+                            UPPER_R => Some(http::HttpReport),
+                            UPPER_S => Some(http::HttpSearch),      // or SUBSCRIBE
+                            UPPER_T => Some(http::HttpTrace),
+                            UPPER_U => Some(http::HttpUnlink),      // or UNLOCK, UNSUBSCRIBE
+                            _   => { self.state = Crashed; return Err(InvalidMethod) },
+                        };
+                        if self.state != StartAnyH {
+                            self.parser_type = ParseRequest;
+                            handler.on_message_begin(self);
+                            self.state = ReqMethod;
+                            self.index = 1;
+                        }
+                    }
+                    StartAnyH => {
+                        match byte {
+                            UPPER_T => {
+                                self.parser_type = ParseResponse;
+                                handler.on_message_begin(self);
+                                self.state = HttpStart;
+                                self.index = 2;
+                            }
+                            UPPER_E => {
+                                self.parser_type = ParseRequest;
+                                self.method = Some(http::HttpHead);
+                                handler.on_message_begin(self);
+                                self.state = ReqMethod;
+                                self.index = 2;
+                            }
+                            _ => { self.state = Crashed; return Err(InvalidMethod) },
+                        }
+                    }
                     StartReq => {
                         self.method = Some(match byte {
                             UPPER_C => http::HttpConnect,     // or CHECKOUT, COPY
@@ -216,9 +640,18 @@ impl Parser {
                     ReqMethod => {
                         let method = self.method.unwrap();
                         if byte == SPACE {
+                            if method == http::HttpConnect {
+                                self.upgrade = true;
+                            }
                             handler.on_method(self, method);
                             self.state = ReqUrl;
                             self.index = 0;
+                        } else if method == http::HttpPropPatch && self.index == 2 && byte == UPPER_I {
+                            // "PRI" so far, which no real method continues with;
+                            // the only request line starting this way is the
+                            // HTTP/2 client connection preface.
+                            self.state = H2Preface;
+                            self.index = 3;
                         } else {
                             if !method.hit(self.index, byte as char) {
                                 self.method = Some(match (method, self.index, byte) {
@@ -251,6 +684,15 @@ impl Parser {
                                 let end = read - 1;
                                 handler.write(self, data.slice(start, end));
                                 handler.on_url(self, self.index);
+                                if self.url_decode {
+                                    match self.emit_url_components(handler) {
+                                        Ok(())   => {}
+                                        Err(err) => {
+                                            self.state = Crashed;
+                                            return Err(err);
+                                        }
+                                    }
+                                }
                                 self.state = HttpStart;
                                 self.index = 0;
                             }
@@ -261,6 +703,15 @@ impl Parser {
                                 let end = read - 1;
                                 handler.write(self, data.slice(start, end));
                                 handler.on_url(self, self.index);
+                                if self.url_decode {
+                                    match self.emit_url_components(handler) {
+                                        Ok(())   => {}
+                                        Err(err) => {
+                                            self.state = Crashed;
+                                            return Err(err);
+                                        }
+                                    }
+                                }
                                 self.state = Dead;
                                 self.index = 0;
                                 handler.on_message_complete(self);
@@ -268,6 +719,13 @@ impl Parser {
                             }
                             _ => {
                                 self.index += 1;
+                                if self.index > self.limits.max_url_length {
+                                    self.state = Crashed;
+                                    return Err(UrlTooLong);
+                                }
+                                if self.url_decode {
+                                    self.url_buffer.push(byte);
+                                }
                             }
                         }
                     }
@@ -315,7 +773,6 @@ impl Parser {
                                     v => {
                                         handler.on_version(self, v.unwrap());
                                         self.http_version = v;
-                                        self.keep_alive = v == Some(http::HTTP_1_1);
                                         self.state = match (byte, self.parser_type) {
                                             (CR, ParseRequest) => ReqLineAlmostDone,
                                             (LF, ParseRequest) => HeaderFieldStart,
@@ -352,11 +809,26 @@ impl Parser {
                             self.index = 0;
                         }
                     }
+                    // The reason phrase itself is never forwarded to the
+                    // handler: `on_status` already carries everything a
+                    // caller acts on (the numeric status code), and
+                    // `MessageHandler` has no matching length callback to
+                    // mark where reason-phrase text ends the way
+                    // `on_header_value` does for headers. So this state
+                    // only counts bytes against `max_status_line_length`,
+                    // same as it always has.
                     ResStatus => {
                         self.state = match byte {
                             CR => ResLineAlmostDone,
                             LF => HeaderFieldStart,
-                            _   => ResStatus, // ignore reason phrases
+                            _   => {
+                                self.index += 1;
+                                if self.index > self.limits.max_status_line_length {
+                                    self.state = Crashed;
+                                    return Err(InvalidStatusLine);
+                                }
+                                ResStatus
+                            }
                         };
                     }
                     ResLineAlmostDone => {
@@ -367,18 +839,24 @@ impl Parser {
                         match byte {
                             CR => self.state = HeadersAlmostDone,
                             LF => {
-                                if handler.on_headers_complete(self) || self.skip_body {
-                                    handler.on_message_complete(self);
-                                    self.reset();
+                                let headers_done = handler.on_headers_complete(self);
+                                if self.expect_continue && !handler.on_expect_continue(self) {
+                                    self.skip_body = true;
+                                }
+                                if headers_done || self.upgrade || self.skip_body {
+                                    if self.upgrade { handler.on_upgrade(self, http::Protocol::Tunnel) }
+                                    if self.complete_message(handler) { continue }
+                                } else if self.chunked {
+                                    self.state = ChunkSize;
+                                    self.message_body_rest = 0;
+                                    self.index = 0;
                                 } else {
                                     match self.message_body_rest {
                                         0u => {
-                                            handler.on_message_complete(self);
-                                            self.reset();
+                                            if self.complete_message(handler) { continue }
                                         }
                                         UINT_MAX => if self.parser_type == ParseRequest || !self.needs_eof() {
-                                            handler.on_message_complete(self);
-                                            self.reset();
+                                            if self.complete_message(handler) { continue }
                                         } else {
                                             self.state = BodyIdentityEOF;
                                         },
@@ -391,6 +869,7 @@ impl Parser {
                                 self.state = HeaderField;
                                 self.hstate = match byte {
                                     UPPER_C | LOWER_C => HeaderConnection,
+                                    UPPER_E | LOWER_E => HeaderExpect,
                                     UPPER_T | LOWER_T => HeaderTransferEncoding,
                                     UPPER_U | LOWER_U => HeaderUpgrade,
                                     _                 => HeaderGeneral,
@@ -407,8 +886,13 @@ impl Parser {
                                 let end = read - 1;
                                 handler.write(self, data.slice(start, end));
                                 handler.on_header_field(self, self.index);
+                                if self.hstate == HeaderUpgrade && self.index == 7 {
+                                    self.has_upgrade_header = true;
+                                }
+                                self.header_kind = self.hstate;
                                 self.state = HeaderValueDiscardWS;
                                 self.index = 0;
+                                self.token_start = 0;
                             }
                             CR => {
                                 self.state = HeaderAlmostDone;
@@ -465,6 +949,23 @@ impl Parser {
                                                 | (UPPER_G, 16) | (LOWER_G, 16) => HeaderTransferEncoding,
                                             _ => HeaderGeneral,
                                         },
+                                        HeaderUpgrade => match (byte, self.index) {
+                                            (UPPER_P, 1) | (LOWER_P, 1)
+                                                | (UPPER_G, 2) | (LOWER_G, 2)
+                                                | (UPPER_R, 3) | (LOWER_R, 3)
+                                                | (UPPER_A, 4) | (LOWER_A, 4)
+                                                | (UPPER_D, 5) | (LOWER_D, 5)
+                                                | (UPPER_E, 6) | (LOWER_E, 6) => HeaderUpgrade,
+                                            _ => HeaderGeneral,
+                                        },
+                                        HeaderExpect => match (byte, self.index) {
+                                            (UPPER_X, 1) | (LOWER_X, 1)
+                                                | (UPPER_P, 2) | (LOWER_P, 2)
+                                                | (UPPER_E, 3) | (LOWER_E, 3)
+                                                | (UPPER_C, 4) | (LOWER_C, 4)
+                                                | (UPPER_T, 5) | (LOWER_T, 5) => HeaderExpect,
+                                            _ => HeaderGeneral,
+                                        },
                                         _ => HeaderGeneral,
                                     };
                                 }
@@ -488,12 +989,21 @@ impl Parser {
                                         | (HeaderConnection, LOWER_U) => HeaderMatchingUpgrade,
                                     (HeaderTransferEncoding, UPPER_C)
                                         | (HeaderTransferEncoding, LOWER_C) => HeaderMatchingChunked,
+                                    (HeaderExpect, ONE) => HeaderMatchingContinue,
                                     (HeaderContentLength, _) => {
+                                        if self.chunked {
+                                            self.state = Crashed;
+                                            return Err(AmbiguousMessageLength);
+                                        }
+                                        self.has_content_length = true;
                                         self.message_body_rest = (byte - ZERO) as uint;
                                         HeaderContentLength
                                     },
                                     _ => HeaderGeneral,
                                 };
+                                if self.header_kind == HeaderUpgrade {
+                                    self.upgrade_buffer.push(byte);
+                                }
                                 self.state = HeaderValue;
                                 self.index += 1;
                             },
@@ -510,24 +1020,33 @@ impl Parser {
                             // header value is empty.
                             handler.on_header_value(self, 0);
                             self.index = 0;
+                            self.header_size = 0;
+                            self.header_count += 1;
+                            if self.header_count > self.limits.max_headers {
+                                self.state = Crashed;
+                                return Err(TooManyHeaders);
+                            }
                             match byte {
                                 CR => self.state = HeadersAlmostDone,
                                 LF => {
-                                    if handler.on_headers_complete(self) || self.upgrade || self.skip_body {
-                                        handler.on_message_complete(self);
-                                        self.reset();
+                                    let headers_done = handler.on_headers_complete(self);
+                                    if self.expect_continue && !handler.on_expect_continue(self) {
+                                        self.skip_body = true;
+                                    }
+                                    if headers_done || self.upgrade || self.skip_body {
+                                        if self.upgrade { handler.on_upgrade(self, http::Protocol::Tunnel) }
+                                        if self.complete_message(handler) { continue }
                                     } else if self.chunked {
                                         self.state = ChunkSize;
                                         self.message_body_rest = 0;
+                                        self.index = 0;
                                     } else {
                                         match self.message_body_rest {
                                             0u => {
-                                                handler.on_message_complete(self);
-                                                self.reset();
+                                                if self.complete_message(handler) { continue }
                                             }
                                             UINT_MAX => if self.parser_type == ParseRequest || !self.needs_eof() {
-                                                handler.on_message_complete(self);
-                                                self.reset();
+                                                if self.complete_message(handler) { continue }
                                             } else {
                                                 self.state = BodyIdentityEOF;
                                             },
@@ -552,11 +1071,18 @@ impl Parser {
                                 } else {
                                     HeaderFieldStart
                                 };
-                                match (self.hstate, self.index) {
-                                    (HeaderMatchingChunked, 7)    => self.chunked = true,
-                                    (HeaderMatchingClose, 5)      => self.keep_alive = false,
-                                    (HeaderMatchingKeepAlive, 10) => self.keep_alive = true,
-                                    (HeaderMatchingUpgrade, 6)    => self.upgrade = true,
+                                match (self.hstate, self.index - self.token_start) {
+                                    (HeaderMatchingChunked, 7) => {
+                                        if self.has_content_length {
+                                            self.state = Crashed;
+                                            return Err(AmbiguousMessageLength);
+                                        }
+                                        self.chunked = true;
+                                    }
+                                    (HeaderMatchingClose, 5)      => self.connection_close = true,
+                                    (HeaderMatchingKeepAlive, 10) => self.connection_keep_alive = true,
+                                    (HeaderMatchingUpgrade, 7)    => self.upgrade = true,
+                                    (HeaderMatchingContinue, 12)  => self.expect_continue = true,
                                     _ => (),
                                 }
                                 let start = if read > self.index + 1 { read - self.index - 1 } else { 0 };
@@ -564,11 +1090,49 @@ impl Parser {
                                 handler.write(self, data.slice(start, end));
                                 handler.on_header_value(self, self.index);
                                 self.index = 0;
+                                self.token_start = 0;
+                                self.header_size = 0;
+                                self.header_count += 1;
+                                if self.header_count > self.limits.max_headers {
+                                    self.state = Crashed;
+                                    return Err(TooManyHeaders);
+                                }
+                            }
+                            COMMA if self.header_kind == HeaderConnection => {
+                                match (self.hstate, self.index - self.token_start) {
+                                    (HeaderMatchingClose, 5)      => self.connection_close = true,
+                                    (HeaderMatchingKeepAlive, 10) => self.connection_keep_alive = true,
+                                    (HeaderMatchingUpgrade, 7)    => self.upgrade = true,
+                                    _ => (),
+                                }
+                                self.hstate = HeaderConnection;
+                                self.index += 1;
+                                self.token_start = self.index;
+                            }
+                            COMMA if self.header_kind == HeaderTransferEncoding => {
+                                // Only the last coding decides chunked framing
+                                // (checked when the value ends), so a `chunked`
+                                // followed by another coding is not recorded.
+                                self.hstate = HeaderTransferEncoding;
+                                self.index += 1;
+                                self.token_start = self.index;
                             }
                             _ => {
                                 if self.hstate != HeaderGeneral {
                                     self.hstate = match (self.hstate, byte) {
-                                        (HeaderMatchingKeepAlive, _) => match (byte, self.index) {
+                                        (HeaderConnection, SPACE) | (HeaderConnection, TAB) => {
+                                            self.token_start += 1;
+                                            HeaderConnection
+                                        }
+                                        (HeaderConnection, UPPER_C) | (HeaderConnection, LOWER_C) => HeaderMatchingClose,
+                                        (HeaderConnection, UPPER_K) | (HeaderConnection, LOWER_K) => HeaderMatchingKeepAlive,
+                                        (HeaderConnection, UPPER_U) | (HeaderConnection, LOWER_U) => HeaderMatchingUpgrade,
+                                        (HeaderTransferEncoding, SPACE) | (HeaderTransferEncoding, TAB) => {
+                                            self.token_start += 1;
+                                            HeaderTransferEncoding
+                                        }
+                                        (HeaderTransferEncoding, UPPER_C) | (HeaderTransferEncoding, LOWER_C) => HeaderMatchingChunked,
+                                        (HeaderMatchingKeepAlive, _) => match (byte, self.index - self.token_start) {
                                             (UPPER_E, 1) | (LOWER_E, 1)
                                                 | (UPPER_E, 2) | (LOWER_E, 2)
                                                 | (UPPER_P, 3) | (LOWER_P, 3)
@@ -580,14 +1144,14 @@ impl Parser {
                                                 | (UPPER_E, 9) | (LOWER_E, 9) => HeaderMatchingKeepAlive,
                                             _ => HeaderGeneral,
                                         },
-                                        (HeaderMatchingClose, _) => match (byte, self.index) {
+                                        (HeaderMatchingClose, _) => match (byte, self.index - self.token_start) {
                                             (UPPER_L, 1) | (LOWER_L, 1)
                                                 | (UPPER_O, 2) | (LOWER_O, 2)
                                                 | (UPPER_S, 3) | (LOWER_S, 3)
                                                 | (UPPER_E, 4) | (LOWER_E, 4) => HeaderMatchingClose,
                                             _ => HeaderGeneral,
                                         },
-                                        (HeaderMatchingChunked, _) => match (byte, self.index) {
+                                        (HeaderMatchingChunked, _) => match (byte, self.index - self.token_start) {
                                             (UPPER_H, 1) | (LOWER_H, 1)
                                                 | (UPPER_U, 2) | (LOWER_U, 2)
                                                 | (UPPER_N, 3) | (LOWER_N, 3)
@@ -596,7 +1160,7 @@ impl Parser {
                                                 | (UPPER_D, 6) | (LOWER_D, 6) => HeaderMatchingChunked,
                                             _ => HeaderGeneral,
                                         },
-                                        (HeaderMatchingUpgrade, _) => match (byte, self.index) {
+                                        (HeaderMatchingUpgrade, _) => match (byte, self.index - self.token_start) {
                                             (UPPER_P, 1) | (LOWER_P, 1)
                                                 | (UPPER_G, 2) | (LOWER_G, 2)
                                                 | (UPPER_R, 3) | (LOWER_R, 3)
@@ -606,17 +1170,38 @@ impl Parser {
                                             _ => HeaderGeneral,
                                         },
                                         (HeaderContentLength, ZERO..NINE) => {
+                                            let digit = (byte - ZERO) as uint;
+                                            if self.message_body_rest > (UINT_MAX - digit) / 10 {
+                                                self.state = Crashed;
+                                                return Err(InvalidContentLength);
+                                            }
                                             self.message_body_rest *= 10;
-                                            self.message_body_rest += (byte - ZERO) as uint;
+                                            self.message_body_rest += digit;
                                             HeaderContentLength
                                         }
                                         (HeaderContentLength, _) => {
                                             self.message_body_rest = UINT_MAX;
                                             HeaderGeneral
                                         }
+                                        (HeaderMatchingContinue, _) => match (byte, self.index) {
+                                            (ZERO, 1) | (ZERO, 2) => HeaderMatchingContinue,
+                                            (HYPHEN, 3) => HeaderMatchingContinue,
+                                            (UPPER_C, 4) | (LOWER_C, 4)
+                                                | (UPPER_O, 5) | (LOWER_O, 5)
+                                                | (UPPER_N, 6) | (LOWER_N, 6)
+                                                | (UPPER_T, 7) | (LOWER_T, 7)
+                                                | (UPPER_I, 8) | (LOWER_I, 8)
+                                                | (UPPER_N, 9) | (LOWER_N, 9)
+                                                | (UPPER_U, 10) | (LOWER_U, 10)
+                                                | (UPPER_E, 11) | (LOWER_E, 11) => HeaderMatchingContinue,
+                                            _ => HeaderGeneral,
+                                        },
                                         _ => HeaderGeneral,
                                     };
                                 }
+                                if self.header_kind == HeaderUpgrade {
+                                    self.upgrade_buffer.push(byte);
+                                }
                                 self.index += 1;
                             }
                         }
@@ -627,21 +1212,23 @@ impl Parser {
                     }
                     HeadersAlmostDone => {
                         if byte != LF { self.state = Crashed; return Err(InvalidHeaders) }
-                        if handler.on_headers_complete(self) || self.upgrade || self.skip_body {
-                            handler.on_message_complete(self);
-                            self.reset();
+                        let headers_done = handler.on_headers_complete(self);
+                        if self.expect_continue && !handler.on_expect_continue(self) {
+                            self.skip_body = true;
+                        }
+                        if headers_done || self.upgrade || self.skip_body {
+                            if self.upgrade { handler.on_upgrade(self, http::Protocol::Tunnel) }
+                            if self.complete_message(handler) { continue }
                         } else if self.chunked {
                             self.state = ChunkSize;
                             self.message_body_rest = 0;
                         } else {
                             match self.message_body_rest {
                                 0u => {
-                                    handler.on_message_complete(self);
-                                    self.reset();
+                                    if self.complete_message(handler) { continue }
                                 }
                                 UINT_MAX => if self.parser_type == ParseRequest || !self.needs_eof() {
-                                    handler.on_message_complete(self);
-                                    self.reset();
+                                    if self.complete_message(handler) { continue }
                                 } else {
                                     self.state = BodyIdentityEOF;
                                 },
@@ -651,13 +1238,49 @@ impl Parser {
 
                         break
                     }
+                    H2Preface => {
+                        if byte != H2_PREFACE[self.index] {
+                            self.state = Crashed;
+                            return Err(InvalidMethod);
+                        }
+                        self.index += 1;
+                        if self.index == H2_PREFACE.len() {
+                            self.saw_h2_preface = true;
+                            handler.on_upgrade(self, http::Protocol::H2);
+                            self.state = Dead;
+                            break;
+                        }
+                    }
+                    // A trailer section cut short by the end of an earlier
+                    // `parse` call resumes here: hand the byte back and let
+                    // the chunked loop below pick the trailer up again.
+                    TrailerStart | Trailer | TrailerAlmostDone | TrailersAlmostDone => {
+                        read -= 1;
+                        break;
+                    }
                     BodyIdentity | BodyIdentityEOF
                         | ChunkSize | ChunkSizeAlmostDone | ChunkExtension | ChunkData
                         | Dead | Crashed => unreachable!(),
                 }
+                if counting_header {
+                    self.header_size += 1;
+                    self.headers_size += 1;
+                    if self.header_size > self.limits.max_header_size
+                        || self.headers_size > self.limits.max_headers_size {
+                        self.state = Crashed;
+                        return Err(HeaderOverflow);
+                    }
+                }
             }
         }
 
+        // `Transfer-Encoding: chunked` bypasses the Content-Length-driven
+        // `BodyIdentity`/`BodyIdentityEOF` states entirely: `message_body_rest`
+        // here tracks bytes left in the *current chunk* rather than the whole
+        // body, so it's free to be re-filled by `ChunkSize` as many times as
+        // the stream has chunks, across as many `parse` calls as it takes to
+        // see them all. Only the data bytes of each chunk reach the handler;
+        // the size line, `;chunk-ext` and the chunk's trailing CRLF never do.
         if self.chunked {
             'chunk: loop {
                 if self.state == ChunkData {
@@ -668,16 +1291,40 @@ impl Parser {
                             return Err(InvalidChunk);
                         }
                         read += 2;
+                        handler.on_chunk_complete(self);
                         self.state = ChunkSize;
+                        self.index = 0;
                     } else if rest >= self.message_body_rest {
-                        handler.write(self, data.slice(read, read + self.message_body_rest));
+                        let chunk = data.slice(read, read + self.message_body_rest);
+                        if self.streaming {
+                            if handler.on_body_data(self, chunk).is_err() {
+                                self.state = Crashed;
+                                return Err(AbortedByHandler);
+                            }
+                        } else {
+                            handler.write(self, chunk);
+                        }
                         read += self.message_body_rest;
                         self.message_body_rest = 0;
                         if data.len() - read < 2 { break 'chunk }
+                        if data[read] != CR || data[read+1] != LF {
+                            self.state = Crashed;
+                            return Err(InvalidChunk);
+                        }
                         read += 2;
+                        handler.on_chunk_complete(self);
                         self.state = ChunkSize;
+                        self.index = 0;
                     } else {
-                        handler.write(self, data.slice_from(read));
+                        let chunk = data.slice_from(read);
+                        if self.streaming {
+                            if handler.on_body_data(self, chunk).is_err() {
+                                self.state = Crashed;
+                                return Err(AbortedByHandler);
+                            }
+                        } else {
+                            handler.write(self, chunk);
+                        }
                         read += rest;
                         self.message_body_rest -= rest;
                         break 'chunk;
@@ -691,30 +1338,87 @@ impl Parser {
                             }
                             (ChunkExtension, _) => { /* ignore */ }
                             (ChunkSize, SEMICOLON) => {
+                                if self.index == 0 { self.state = Crashed; return Err(InvalidChunkSize) }
                                 self.state = ChunkExtension;
                             }
                             (ChunkSize, CR) => {
+                                if self.index == 0 { self.state = Crashed; return Err(InvalidChunkSize) }
                                 self.state = ChunkSizeAlmostDone;
                             }
                             (ChunkSize, _) => {
-                                let val = unhex(byte);
-                                if val > 15 { self.state = Crashed; return Err(InvalidChunk) }
+                                let val = hex_digit(byte);
+                                if val > 15 { self.state = Crashed; return Err(InvalidChunkSize) }
+                                if self.message_body_rest > (UINT_MAX - val) / 16 {
+                                    self.state = Crashed;
+                                    return Err(InvalidChunk);
+                                }
                                 self.message_body_rest *= 16;
                                 self.message_body_rest += val;
+                                self.index += 1;
                             }
                             (ChunkSizeAlmostDone, _) => {
                                 if byte != LF { self.state = Crashed; return Err(InvalidChunk) }
                                 if self.message_body_rest == 0 {
-                                    handler.on_message_complete(self);
-                                    break 'chunk;
+                                    self.state = TrailerStart;
+                                    self.index = 0;
                                 } else {
+                                    handler.on_chunk_header(self, self.message_body_rest);
                                     self.state = ChunkData;
                                     break 'chunksize;
                                 }
                             }
+                            (TrailerStart, CR) => {
+                                self.state = TrailersAlmostDone;
+                            }
+                            (TrailerStart, LF) => {
+                                self.complete_message(handler);
+                                break 'chunk;
+                            }
+                            (TrailerStart, _) => {
+                                self.state = Trailer;
+                                self.index = 1;
+                            }
+                            (Trailer, CR) => {
+                                self.state = TrailerAlmostDone;
+                            }
+                            (Trailer, LF) => {
+                                let start = if read > self.index + 1 { read - self.index - 1 } else { 0 };
+                                let end = read - 1;
+                                handler.write(self, data.slice(start, end));
+                                handler.on_trailer(self, self.index);
+                                self.index = 0;
+                                self.state = TrailerStart;
+                            }
+                            (Trailer, _) => {
+                                self.index += 1;
+                            }
+                            (TrailerAlmostDone, _) => {
+                                if byte != LF { self.state = Crashed; return Err(InvalidHeaders) }
+                                let start = if read > self.index + 2 { read - self.index - 2 } else { 0 };
+                                let end = read - 2;
+                                handler.write(self, data.slice(start, end));
+                                handler.on_trailer(self, self.index);
+                                self.index = 0;
+                                self.state = TrailerStart;
+                            }
+                            (TrailersAlmostDone, _) => {
+                                if byte != LF { self.state = Crashed; return Err(InvalidHeaders) }
+                                self.complete_message(handler);
+                                break 'chunk;
+                            }
                             _ => unreachable!()
                         }
                     }
+                    // The `for` loop above only `break`s once a chunk-size
+                    // line (or the trailer section) is fully parsed; if
+                    // `data` ran out first it just falls through having
+                    // made no further progress, and `self.state` is still
+                    // one of the chunksize/trailer states above, not
+                    // `ChunkData`. Without this, the next turn of `'chunk`
+                    // would re-enter this same `else` branch over an
+                    // already-exhausted slice and spin forever instead of
+                    // returning so the caller can supply more bytes.
+                    if read == data.len() { break 'chunk }
                 }
             }
         }
@@ -723,19 +1427,42 @@ impl Parser {
             BodyIdentity => {
                 let rest = data.len() - read;
                 if rest >= self.message_body_rest {
-                    handler.write(self, data.slice(read, read + self.message_body_rest));
-                    handler.on_body(self, self.message_body_rest);
-                    handler.on_message_complete(self);
+                    let chunk = data.slice(read, read + self.message_body_rest);
+                    if self.streaming {
+                        if handler.on_body_data(self, chunk).is_err() {
+                            self.state = Crashed;
+                            return Err(AbortedByHandler);
+                        }
+                    } else {
+                        handler.write(self, chunk);
+                        handler.on_body(self, self.message_body_rest);
+                    }
                     read += self.message_body_rest;
-                    self.reset();
+                    self.complete_message(handler);
                 } else {
-                    handler.write(self, data.slice_from(read));
+                    let chunk = data.slice_from(read);
+                    if self.streaming {
+                        if handler.on_body_data(self, chunk).is_err() {
+                            self.state = Crashed;
+                            return Err(AbortedByHandler);
+                        }
+                    } else {
+                        handler.write(self, chunk);
+                    }
                     read += rest;
                     self.message_body_rest -= rest;
                 }
             }
             BodyIdentityEOF if data.len() != read => {
-                handler.write(self, data.slice_from(read));
+                let chunk = data.slice_from(read);
+                if self.streaming {
+                    if handler.on_body_data(self, chunk).is_err() {
+                        self.state = Crashed;
+                        return Err(AbortedByHandler);
+                    }
+                } else {
+                    handler.write(self, chunk);
+                }
             }
             ReqUrl | HeaderField | HeaderValue => {
                 let start = if read > self.index { read - self.index } else { 0 };
@@ -747,13 +1474,128 @@ impl Parser {
         return Ok(read);
     }
 
-    /// Connection: keep-alive or Connection: close
+    /// Drain as many complete, pipelined messages as `buf` holds, instead
+    /// of the caller hand-tracking a read offset and copying leftover bytes
+    /// into a scratch buffer between calls to `parse`. Stops once
+    /// `max_messages` complete messages have been produced even if `buf`
+    /// holds more, so a read full of tiny pipelined requests can't make a
+    /// single call do unbounded work; feed the unconsumed remainder
+    /// (`result.consumed`) to another `parse_all` call to continue. Because
+    /// a single `parse` call can itself complete more than one bodyless
+    /// pipelined message before returning, the count is only checked
+    /// between `parse` calls, not between individual messages.
+    pub fn parse_all<C: MessageHandler>(&mut self, buf: &[u8], handler: &mut C, max_messages: uint) -> Result<ParseAllResult, ParseError> {
+        let mut consumed = 0u;
+        let mut messages = 0u;
+        let mut ran_dry = false;
+        while consumed < buf.len() && messages < max_messages && self.state != Dead {
+            let mut counting = CountingHandler { inner: handler, messages: 0 };
+            let read = try!(self.parse(buf.slice_from(consumed), &mut counting));
+            messages += counting.messages;
+            consumed += read;
+            if read == 0 {
+                ran_dry = true;
+                break;
+            }
+        }
+        let needs_more = !ran_dry
+            && consumed == buf.len()
+            && self.state != Dead
+            && self.state != StartReq
+            && self.state != StartRes;
+        Ok(ParseAllResult { consumed: consumed, messages: messages, needs_more: needs_more })
+    }
+
+    /// Whether the connection should be kept alive once the message is
+    /// complete, taking the HTTP version and any `Connection` tokens into
+    /// account. Reflects the decision made for the most recently completed
+    /// message: `parse` itself resets per-message state (including the
+    /// `Connection` tokens this is based on) as soon as it decides to carry
+    /// on to a pipelined message, so this can't simply be recomputed from
+    /// live fields after the fact.
     pub fn should_keep_alive(&self) -> bool {
-        self.keep_alive
+        self.pipeline_keep_alive
+    }
+
+    /// Whether the current message leaves the connection persistent. A
+    /// `close` or `upgrade` token (or a `CONNECT` request) always ends it;
+    /// otherwise HTTP/1.1 is persistent by default, while HTTP/1.0 (and
+    /// anything earlier) is only with a `Connection: keep-alive` token.
+    #[inline]
+    fn compute_keep_alive(&self) -> bool {
+        if self.connection_close || self.upgrade {
+            return false;
+        }
+        match self.http_version {
+            Some(http::HTTP_1_1) => true,
+            _ => self.connection_keep_alive,
+        }
     }
 
-    /// Connection: upgrade
+    /// Connection: upgrade, together with an Upgrade: header (e.g. websocket).
+    /// Narrower than `is_upgrade`: a bare `CONNECT` tunnel carries no
+    /// `Upgrade:` header and so is `is_upgrade() == true` but
+    /// `should_upgrade() == false` here.
     pub fn should_upgrade(&self) -> bool {
+        self.upgrade && self.has_upgrade_header
+    }
+
+    /// The `Upgrade` header's raw value, e.g. `"websocket"`, once
+    /// `should_upgrade()` is true. `parse` has already stopped at the end
+    /// of the header block by then and handed back the offset of the
+    /// first post-handshake byte as its `Ok(read)`, so a caller can read
+    /// this to pick the right protocol, validate e.g. `Sec-WebSocket-Key`
+    /// out of band, and take over the remaining bytes of `data` itself
+    /// instead of feeding them back to this parser. `None` if no `Upgrade`
+    /// header was seen, or its value wasn't valid UTF-8.
+    pub fn upgrade_protocol(&self) -> Option<&str> {
+        if !self.has_upgrade_header { return None }
+        from_utf8(self.upgrade_buffer.as_slice())
+    }
+
+    /// Whether `parse` stopped consuming input right after the header
+    /// block because this message hands off to an opaque tunnel: a
+    /// `CONNECT` request, or a `Connection: upgrade` negotiated protocol
+    /// switch (e.g. WebSocket). Any bytes in the buffer past what `parse`
+    /// reported as consumed belong to that tunnel, not to this parser.
+    pub fn is_upgrade(&self) -> bool {
+        self.upgrade
+    }
+
+    /// Whether `parse` stopped because it recognized the start of an
+    /// HTTP/2 client connection preface instead of an HTTP/1 request
+    /// line. Once this is true the parser has entered a terminal state
+    /// and will not decode any further HTTP/1 messages; the caller should
+    /// hand the connection off to an HTTP/2 stack.
+    ///
+    /// This can be true before the whole 24-octet preface has arrived: a
+    /// buffer that only carries a prefix of it (e.g. a lone `"PRI"`) still
+    /// reports true here, since every byte seen so far matched and nothing
+    /// else begins a request line the same way. `parse` keeps consuming
+    /// from that same state on the next call, so only a non-matching byte
+    /// downstream would ever turn this back into `InvalidMethod`.
+    pub fn is_http2_preface(&self) -> bool {
+        self.state == H2Preface || (self.state == Dead && self.saw_h2_preface)
+    }
+
+    /// Alias for `is_http2_preface`, named to match `should_upgrade`: both
+    /// report that `parse` stopped short of a full HTTP/1 message because
+    /// the connection is switching protocols, and in both cases `Ok(read)`
+    /// already carries the offset of the first byte the caller should hand
+    /// off rather than feed back in.
+    pub fn should_upgrade_h2(&self) -> bool {
+        self.is_http2_preface()
+    }
+
+    /// The `Connection` header carried a `close` token.
+    pub fn connection_close(&self) -> bool {
+        self.connection_close
+    }
+
+    /// The `Connection` header carried an `upgrade` token, or the request
+    /// is a `CONNECT`: both set the same flag `is_upgrade` reports, so a
+    /// `CONNECT` with no `Connection` header also returns true here.
+    pub fn connection_upgrade(&self) -> bool {
         self.upgrade
     }
 
@@ -762,11 +1604,72 @@ impl Parser {
         self.chunked
     }
 
+    /// The request carried an `Expect: 100-continue` header, matched the
+    /// same byte-by-byte case-insensitive way as the `Connection` and
+    /// `Content-Length` field names and values. Raised before
+    /// `on_headers_complete` calls `MessageHandler::on_expect_continue`, so
+    /// a handler inspecting `Parser` from inside that callback already sees
+    /// it set; `should_send_continue` additionally restricts it to HTTP/1.1,
+    /// the only version a `100 Continue` status line applies to.
+    pub fn expects_continue(&self) -> bool {
+        self.expect_continue
+    }
+
+    /// Whether a handler should write `HTTP/1.1 100 Continue\r\n\r\n` before
+    /// consuming the body: the request carried an `Expect: 100-continue`
+    /// header and is HTTP/1.1, the only version this status line applies
+    /// to. Side-effect-free and safe to call any number of times from
+    /// `on_headers_complete`; `reset` clears `expect_continue` between
+    /// pipelined messages so each is judged on its own headers.
+    pub fn should_send_continue(&self) -> bool {
+        self.expect_continue && self.http_version == Some(http::HTTP_1_1)
+    }
+
+    /// Decompose the buffered request target and report each piece via
+    /// `MessageHandler::on_url_component`, then clear the buffer. Only
+    /// called when `url_decode` is enabled.
+    #[inline]
+    fn emit_url_components<C: MessageHandler>(&mut self, handler: &mut C) -> Result<(), ParseError> {
+        let components = decompose_url(self.method, self.url_buffer.as_slice());
+        self.url_buffer.clear();
+        match components {
+            Ok(parts) => {
+                for &(ref kind, ref bytes) in parts.iter() {
+                    handler.on_url_component(self, kind.clone(), bytes.as_slice());
+                }
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Finish the current message and, on a keep-alive connection, start a
+    /// new one in place so `parse` can go on consuming any pipelined bytes
+    /// still left in `data` without the caller allocating a fresh `Parser`.
+    /// Returns whether it did so; callers that are mid-iteration over
+    /// `data` should keep going on `true` and stop on `false`.
+    #[inline]
+    fn complete_message<C: MessageHandler>(&mut self, handler: &mut C) -> bool {
+        handler.on_message_complete(self);
+        self.pipeline_keep_alive = self.compute_keep_alive();
+        if self.pipeline_keep_alive {
+            self.reset();
+        } else {
+            self.state = Dead;
+        }
+        self.pipeline_keep_alive
+    }
+
     #[inline]
     fn reset(&mut self) {
         self.state = match self.parser_type {
             ParseRequest  => StartReq,
             ParseResponse => StartRes,
+            // Only reachable if a message somehow completed without
+            // `StartAny`/`StartAnyH` ever classifying it and rewriting
+            // `parser_type`; re-entering the sniff keeps a pipelined
+            // connection's next message decodable either way.
+            ParseEither   => StartAny,
         };
         self.index = 0;
         self.major = 0;
@@ -774,6 +1677,19 @@ impl Parser {
         self.message_body_rest = UINT_MAX;
         self.skip_body = false;
         self.status_code = 0;
+        self.has_upgrade_header = false;
+        self.expect_continue = false;
+        self.connection_close = false;
+        self.connection_keep_alive = false;
+        self.chunked = false;
+        self.has_content_length = false;
+        self.header_kind = HeaderGeneral;
+        self.token_start = 0;
+        self.header_count = 0;
+        self.header_size = 0;
+        self.headers_size = 0;
+        self.url_buffer.clear();
+        self.upgrade_buffer.clear();
     }
 
     #[inline]
@@ -784,25 +1700,39 @@ impl Parser {
         if self.status_code / 100 == 1 ||     // 1xx e.g. Continue
             self.status_code == 204 ||        // No Content
             self.status_code == 304 ||        // Not Modified
-            self.skip_body {
+            self.skip_body ||
+            self.chunked {                    // chunked framing ends at the final chunk, not EOF
             return false;
         }
-        // TODO: chanked
         return true;
     }
 }
 
 static TAB: u8       = 0x09;
-static LF: u8        = 0x0a;
-static CR: u8        = 0x0d;
-static SPACE: u8     = 0x20;
+/// A bare line feed, exposed for `encoder` to reuse when framing output.
+pub static LF: u8    = 0x0a;
+/// A bare carriage return, exposed for `encoder` to reuse when framing
+/// output.
+pub static CR: u8    = 0x0d;
+/// An ASCII space, exposed for `encoder` to reuse when framing output.
+pub static SPACE: u8 = 0x20;
+static HASH: u8      = 0x23;
+static PERCENT: u8   = 0x25;
+static ASTERISK: u8  = 0x2a;
+static COMMA: u8     = 0x2c;
 static HYPHEN: u8    = 0x2d;
 static DOT: u8       = 0x2e;
 static SLASH: u8     = 0x2f;
-static ZERO: u8      = 0x30;
+/// The ASCII digit `0`, exposed for `encoder` to reuse when writing
+/// chunk-size digits.
+pub static ZERO: u8  = 0x30;
+static ONE: u8       = 0x31;
 static NINE: u8      = 0x39;
-static COLON: u8     = 0x3a;
+/// A colon, exposed for `encoder` to reuse when writing `name: value`
+/// header lines.
+pub static COLON: u8 = 0x3a;
 static SEMICOLON: u8 = 0x3b;
+static QUESTION: u8  = 0x3f;
 static UPPER_A: u8   = 0x41;
 static UPPER_C: u8   = 0x43;
 static UPPER_D: u8   = 0x44;
@@ -822,7 +1752,10 @@ static UPPER_S: u8   = 0x53;
 static UPPER_T: u8   = 0x54;
 static UPPER_U: u8   = 0x55;
 static UPPER_V: u8   = 0x56;
-static LOWER_A: u8   = 0x61;
+static UPPER_X: u8   = 0x58;
+/// The ASCII letter `a`, exposed for `encoder` to reuse when writing
+/// lowercase hex chunk-size digits.
+pub static LOWER_A: u8 = 0x61;
 static LOWER_C: u8   = 0x63;
 static LOWER_D: u8   = 0x64;
 static LOWER_E: u8   = 0x65;
@@ -840,14 +1773,164 @@ static LOWER_S: u8   = 0x73;
 static LOWER_T: u8   = 0x74;
 static LOWER_U: u8   = 0x75;
 static LOWER_V: u8   = 0x76;
+static LOWER_X: u8   = 0x78;
+
+/// The fixed 24-byte HTTP/2 client connection preface (RFC 7540 section 3.5).
+static H2_PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
 
 #[inline]
-fn unhex(b: u8) -> uint {
-    if b < ZERO || b > NINE { UINT_MAX } else { (b - ZERO) as uint }
+fn hex_digit(b: u8) -> uint {
+    match b {
+        ZERO..NINE      => (b - ZERO) as uint,
+        UPPER_A..UPPER_F => (b - UPPER_A) as uint + 10,
+        LOWER_A..LOWER_F => (b - LOWER_A) as uint + 10,
+        _ => UINT_MAX,
+    }
+}
+
+/// Find the first occurrence of `target` in `data`, or `None`.
+fn find_byte(data: &[u8], target: u8) -> Option<uint> {
+    let mut i = 0u;
+    while i < data.len() {
+        if data[i] == target { return Some(i) }
+        i += 1;
+    }
+    None
+}
+
+/// Find the last occurrence of `target` in `data`, or `None`.
+fn rfind_byte(data: &[u8], target: u8) -> Option<uint> {
+    let mut i = data.len();
+    while i > 0 {
+        i -= 1;
+        if data[i] == target { return Some(i) }
+    }
+    None
+}
+
+/// Decode `%XX` escapes in `raw`. Any other byte is copied through as-is.
+fn percent_decode(raw: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let mut out: Vec<u8> = Vec::with_capacity(raw.len());
+    let mut i = 0u;
+    while i < raw.len() {
+        if raw[i] == PERCENT {
+            if i + 2 >= raw.len() { return Err(InvalidUrlEscape) }
+            let hi = hex_digit(raw[i + 1]);
+            let lo = hex_digit(raw[i + 2]);
+            if hi > 15 || lo > 15 { return Err(InvalidUrlEscape) }
+            out.push((hi * 16 + lo) as u8);
+            i += 3;
+        } else {
+            out.push(raw[i]);
+            i += 1;
+        }
+    }
+    Ok(out)
+}
+
+/// Copy a byte slice into an owned `Vec<u8>`.
+fn bytes_to_vec(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(data.len());
+    out.push_all(data);
+    out
+}
+
+/// Split `authority` into `Host`/`Port` components on the last `:`.
+fn split_authority(authority: &[u8]) -> Vec<(UrlComponent, Vec<u8>)> {
+    let mut parts = Vec::new();
+    match rfind_byte(authority, COLON) {
+        Some(i) => {
+            parts.push((Host, bytes_to_vec(authority.slice_to(i))));
+            parts.push((Port, bytes_to_vec(authority.slice_from(i + 1))));
+        }
+        None => {
+            parts.push((Host, bytes_to_vec(authority)));
+        }
+    }
+    parts
+}
+
+/// Split the query/fragment suffix off of `raw`, returning the remaining
+/// scheme-relative part plus any `Query`/`Fragment` components found.
+fn split_query_fragment(raw: &[u8]) -> (&[u8], Vec<(UrlComponent, Vec<u8>)>) {
+    let mut parts = Vec::new();
+    let (before_fragment, fragment) = match find_byte(raw, HASH) {
+        Some(i) => (raw.slice_to(i), Some(raw.slice_from(i + 1))),
+        None => (raw, None),
+    };
+    let (path, query) = match find_byte(before_fragment, QUESTION) {
+        Some(i) => (before_fragment.slice_to(i), Some(before_fragment.slice_from(i + 1))),
+        None => (before_fragment, None),
+    };
+    match query {
+        Some(q) => parts.push((Query, bytes_to_vec(q))),
+        None => {}
+    }
+    match fragment {
+        Some(f) => parts.push((Fragment, bytes_to_vec(f))),
+        None => {}
+    }
+    (path, parts)
+}
+
+/// Decompose a request target into its structured components. `method` is
+/// consulted to recognize a CONNECT request's authority-form target
+/// (`host:port`, with no scheme or path).
+fn decompose_url(method: Option<http::HttpMethod>, raw: &[u8]) -> Result<Vec<(UrlComponent, Vec<u8>)>, ParseError> {
+    if raw.len() == 1 && raw[0] == ASTERISK {
+        let mut result = Vec::new();
+        result.push((Path, bytes_to_vec(raw)));
+        return Ok(result);
+    }
+
+    if method == Some(http::HttpConnect) {
+        return Ok(split_authority(raw));
+    }
+
+    // absolute-form: scheme "://" authority [ "/" path ] [ "?" query ] [ "#" fragment ]
+    match find_byte(raw, COLON) {
+        Some(scheme_end) if raw.len() > scheme_end + 2 &&
+                             raw[scheme_end + 1] == SLASH &&
+                             raw[scheme_end + 2] == SLASH => {
+            let scheme = raw.slice_to(scheme_end);
+            let rest = raw.slice_from(scheme_end + 3);
+            let authority_end = match find_byte(rest, SLASH) {
+                Some(i) => i,
+                None => rest.len(),
+            };
+            let authority = rest.slice_to(authority_end);
+            let (path, parts) = split_query_fragment(rest.slice_from(authority_end));
+            let decoded_path = match percent_decode(path) {
+                Ok(decoded) => decoded,
+                Err(err) => return Err(err),
+            };
+
+            let mut result = Vec::new();
+            result.push((Scheme, bytes_to_vec(scheme)));
+            result.push_all(split_authority(authority).as_slice());
+            result.push((Path, decoded_path));
+            result.push_all(parts.as_slice());
+            Ok(result)
+        }
+        _ => {
+            // origin-form
+            let (path, parts) = split_query_fragment(raw);
+            let decoded_path = match percent_decode(path) {
+                Ok(decoded) => decoded,
+                Err(err) => return Err(err),
+            };
+            let mut result = Vec::new();
+            result.push((Path, decoded_path));
+            result.push_all(parts.as_slice());
+            Ok(result)
+        }
+    }
 }
 
 #[deriving(PartialEq, Eq, Clone, Show)]
 enum ParserState {
+    StartAny,
+    StartAnyH,
     StartReq,
     StartRes,
     ReqMethod,
@@ -873,6 +1956,11 @@ enum ParserState {
     ChunkSizeAlmostDone,
     ChunkExtension,
     ChunkData,
+    TrailerStart,
+    Trailer,
+    TrailerAlmostDone,
+    TrailersAlmostDone,
+    H2Preface,
     Dead,
     Crashed,
 }
@@ -884,8 +1972,10 @@ enum HeaderState {
     HeaderContentLength,
     HeaderTransferEncoding,
     HeaderUpgrade,
+    HeaderExpect,
     HeaderMatchingChunked,
     HeaderMatchingClose,
     HeaderMatchingKeepAlive,
     HeaderMatchingUpgrade,
+    HeaderMatchingContinue,
 }
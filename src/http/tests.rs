@@ -362,3 +362,310 @@ fn create_response(version: uint, status: &'static str, header: Option<Vec<&'sta
     vec.push(mbody.to_string());
     vec.connect("\r\n")
 }
+
+// Regression coverage for the hex chunk-size decoding bug found in
+// review: `unhex` only handled ASCII `0-9` and crashed the parser on any
+// hex-letter chunk size, which is most chunks ≥10 bytes. Written against
+// `message::CollectingHandler` rather than the legacy `TestHandler` above
+// so it doesn't depend on that fixture's pre-`http::parser`-split API.
+mod chunk_size_hex {
+    use http::parser::{Parser, ParseRequest};
+    use http::message::CollectingHandler;
+
+    #[test]
+    fn test_hex_letters_in_chunk_size() {
+        let msg = "POST /upload HTTP/1.1\r\n\
+                   Host: faultier.jp\r\n\
+                   Transfer-Encoding: chunked\r\n\
+                   \r\n\
+                   1a\r\n\
+                   abcdefghijklmnopqrstuvwxyz\r\n\
+                   0\r\n\
+                   \r\n";
+        let data = msg.as_bytes();
+        let mut parser = Parser::new(ParseRequest);
+        let mut handler = CollectingHandler::new();
+        assert_eq!(parser.parse(data, &mut handler), Ok(data.len()));
+        assert!(handler.is_finished());
+        let message = handler.take_message();
+        assert_eq!(message.body, "abcdefghijklmnopqrstuvwxyz".as_bytes().to_vec());
+    }
+
+    // Regression coverage for the infinite-loop bug found in review: a
+    // `parse` call that runs out of bytes mid-chunk-size-line, or exactly
+    // at a chunk boundary, must return rather than spin re-entering the
+    // chunksize branch over an already-exhausted slice. Feeds the message
+    // across two `parse` calls on the same `Parser`/`CollectingHandler` so
+    // each split point is actually exercised cross-call, not just
+    // cross-iteration within one buffer.
+    #[test]
+    fn test_resumes_across_parse_calls_mid_chunk_size_line() {
+        let head = "POST /upload HTTP/1.1\r\n\
+                    Host: faultier.jp\r\n\
+                    Transfer-Encoding: chunked\r\n\
+                    \r\n\
+                    5\r";
+        let tail = "\nhello\r\n0\r\n\r\n";
+        let mut parser = Parser::new(ParseRequest);
+        let mut handler = CollectingHandler::new();
+        let first = parser.parse(head.as_bytes(), &mut handler);
+        assert_eq!(first, Ok(head.len()));
+        assert!(!handler.is_finished());
+        let second = parser.parse(tail.as_bytes(), &mut handler);
+        assert_eq!(second, Ok(tail.len()));
+        assert!(handler.is_finished());
+        let message = handler.take_message();
+        assert_eq!(message.body, "hello".as_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_resumes_across_parse_calls_at_chunk_boundary() {
+        let head = "POST /upload HTTP/1.1\r\n\
+                    Host: faultier.jp\r\n\
+                    Transfer-Encoding: chunked\r\n\
+                    \r\n\
+                    5\r\nhello\r\n";
+        let tail = "0\r\n\r\n";
+        let mut parser = Parser::new(ParseRequest);
+        let mut handler = CollectingHandler::new();
+        let first = parser.parse(head.as_bytes(), &mut handler);
+        assert_eq!(first, Ok(head.len()));
+        assert!(!handler.is_finished());
+        let second = parser.parse(tail.as_bytes(), &mut handler);
+        assert_eq!(second, Ok(tail.len()));
+        assert!(handler.is_finished());
+        let message = handler.take_message();
+        assert_eq!(message.body, "hello".as_bytes().to_vec());
+    }
+
+    // Regression coverage for the unvalidated trailing CRLF found in
+    // review: the fast path that consumes a chunk's data and its trailing
+    // CRLF in one go skipped straight over those two bytes instead of
+    // checking them, unlike the slow path just above it. Garbage in their
+    // place should be rejected rather than silently desyncing the framing.
+    #[test]
+    fn test_rejects_malformed_crlf_after_chunk_data() {
+        use http::parser::InvalidChunk;
+
+        let msg = "POST /upload HTTP/1.1\r\n\
+                   Host: faultier.jp\r\n\
+                   Transfer-Encoding: chunked\r\n\
+                   \r\n\
+                   5\r\nhelloXX0\r\n\r\n";
+        let data = msg.as_bytes();
+        let mut parser = Parser::new(ParseRequest);
+        let mut handler = CollectingHandler::new();
+        assert_eq!(parser.parse(data, &mut handler), Err(InvalidChunk));
+    }
+}
+
+// Regression coverage for the `Connection` token-folding bug found in
+// review: unlike the `Transfer-Encoding` arm right after it, the
+// `HeaderConnection` whitespace arm didn't advance `token_start` past
+// optional whitespace after a comma, so every token after the first in a
+// folded header was matched against the wrong offsets and silently
+// dropped.
+mod connection_token_folding {
+    use http::parser::{Parser, ParseRequest};
+    use http::message::CollectingHandler;
+
+    #[test]
+    fn test_close_then_upgrade_with_ows() {
+        let msg = "GET /ws HTTP/1.1\r\n\
+                   Host: faultier.jp\r\n\
+                   Connection: close, upgrade\r\n\
+                   Upgrade: websocket\r\n\
+                   \r\n";
+        let data = msg.as_bytes();
+        let mut parser = Parser::new(ParseRequest);
+        let mut handler = CollectingHandler::new();
+        assert_eq!(parser.parse(data, &mut handler), Ok(data.len()));
+        assert!(parser.connection_close());
+        assert!(parser.connection_upgrade());
+        assert!(parser.should_upgrade());
+    }
+
+    #[test]
+    fn test_keep_alive_then_close_with_ows() {
+        let msg = "GET / HTTP/1.1\r\n\
+                   Host: faultier.jp\r\n\
+                   Connection: keep-alive, close\r\n\
+                   \r\n";
+        let data = msg.as_bytes();
+        let mut parser = Parser::new(ParseRequest);
+        let mut handler = CollectingHandler::new();
+        assert_eq!(parser.parse(data, &mut handler), Ok(data.len()));
+        assert!(parser.connection_close());
+        assert!(!parser.should_keep_alive());
+    }
+}
+
+// Coverage for `ParseEither`: a single `Parser` constructed without
+// knowing in advance whether it'll see a request or a response line
+// should classify each of these correctly, including the `HEAD`/`HTTP/`
+// ambiguity the review flagged as needing two bytes of lookahead.
+mod parse_either {
+    use http;
+    use http::parser::{Parser, ParseEither};
+    use http::message::CollectingHandler;
+
+    #[test]
+    fn test_sniffs_a_request() {
+        let msg = "GET / HTTP/1.1\r\nHost: faultier.jp\r\n\r\n";
+        let data = msg.as_bytes();
+        let mut parser = Parser::new(ParseEither);
+        let mut handler = CollectingHandler::new();
+        assert_eq!(parser.parse(data, &mut handler), Ok(data.len()));
+        assert!(handler.is_finished());
+        let message = handler.take_message();
+        assert_eq!(message.method, Some(http::HttpGet));
+        assert_eq!(message.url, Some("/".to_string()));
+    }
+
+    #[test]
+    fn test_sniffs_a_response() {
+        let msg = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+        let data = msg.as_bytes();
+        let mut parser = Parser::new(ParseEither);
+        let mut handler = CollectingHandler::new();
+        assert_eq!(parser.parse(data, &mut handler), Ok(data.len()));
+        assert!(handler.is_finished());
+        let message = handler.take_message();
+        assert_eq!(message.status_code, Some(200u));
+    }
+
+    #[test]
+    fn test_sniffs_head_despite_sharing_a_leading_h_with_http_slash() {
+        let msg = "HEAD / HTTP/1.1\r\nHost: faultier.jp\r\n\r\n";
+        let data = msg.as_bytes();
+        let mut parser = Parser::new(ParseEither);
+        let mut handler = CollectingHandler::new();
+        assert_eq!(parser.parse(data, &mut handler), Ok(data.len()));
+        assert!(handler.is_finished());
+        let message = handler.take_message();
+        assert_eq!(message.method, Some(http::HttpHead));
+        assert_eq!(message.url, Some("/".to_string()));
+    }
+}
+
+// Coverage for `set_url_decode`/`on_url_component`, named in review as an
+// example of the series-wide lack of tests: origin-form with a
+// percent-escaped path plus a query string, and an absolute-form target
+// with scheme/host/port pulled apart.
+mod url_decomposition {
+    use std::str::from_utf8;
+    use http::parser::{Parser, ParseRequest, MessageHandler, UrlComponent};
+    use http::parser::{Scheme, Host, Port, Path, Query};
+
+    struct ComponentHandler {
+        components: Vec<(UrlComponent, String)>,
+    }
+
+    impl ComponentHandler {
+        fn new() -> ComponentHandler {
+            ComponentHandler { components: Vec::new() }
+        }
+    }
+
+    impl MessageHandler for ComponentHandler {
+        fn on_url_component(&mut self, _: &Parser, kind: UrlComponent, bytes: &[u8]) {
+            let value = match from_utf8(bytes) {
+                Some(s) => s.to_string(),
+                None => String::new(),
+            };
+            self.components.push((kind, value));
+        }
+
+        fn write(&mut self, _: &Parser, _: &[u8]) {
+        }
+    }
+
+    #[test]
+    fn test_origin_form_decodes_path_and_splits_query() {
+        let msg = "GET /a%20b?x=1 HTTP/1.1\r\nHost: faultier.jp\r\n\r\n";
+        let data = msg.as_bytes();
+        let mut parser = Parser::new(ParseRequest);
+        parser.set_url_decode(true);
+        let mut handler = ComponentHandler::new();
+        assert_eq!(parser.parse(data, &mut handler), Ok(data.len()));
+        assert_eq!(handler.components, vec![
+            (Path, "a b".to_string()),
+            (Query, "x=1".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_absolute_form_splits_scheme_host_port_and_path() {
+        let msg = "GET http://example.com:8080/path HTTP/1.1\r\nHost: faultier.jp\r\n\r\n";
+        let data = msg.as_bytes();
+        let mut parser = Parser::new(ParseRequest);
+        parser.set_url_decode(true);
+        let mut handler = ComponentHandler::new();
+        assert_eq!(parser.parse(data, &mut handler), Ok(data.len()));
+        assert_eq!(handler.components, vec![
+            (Scheme, "http".to_string()),
+            (Host, "example.com".to_string()),
+            (Port, "8080".to_string()),
+            (Path, "path".to_string()),
+        ]);
+    }
+}
+
+// Coverage for `http::websocket`, named in review as an example of the
+// series' missing test coverage: the handshake accept token against the
+// worked example from RFC 6455 section 1.3, and frame decoding against
+// the masked "Hello" example from section 5.7.
+mod websocket {
+    use http::websocket::{FrameParser, FrameHandler, OpCode, Text};
+
+    #[test]
+    fn test_accept_key_matches_rfc6455_example() {
+        use http::websocket::accept_key;
+        let key = "dGhlIHNhbXBsZSBub25jZQ==".as_bytes();
+        assert_eq!(accept_key(key).as_slice(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    struct CollectingFrameHandler {
+        fin: Option<bool>,
+        opcode: Option<OpCode>,
+        payload_len: Option<uint>,
+        data: Vec<u8>,
+        complete: bool,
+    }
+
+    impl CollectingFrameHandler {
+        fn new() -> CollectingFrameHandler {
+            CollectingFrameHandler { fin: None, opcode: None, payload_len: None, data: Vec::new(), complete: false }
+        }
+    }
+
+    impl FrameHandler for CollectingFrameHandler {
+        fn on_frame_header(&mut self, _: &FrameParser, fin: bool, opcode: OpCode, payload_len: uint) {
+            self.fin = Some(fin);
+            self.opcode = Some(opcode);
+            self.payload_len = Some(payload_len);
+        }
+
+        fn on_frame_data(&mut self, _: &FrameParser, data: &[u8]) {
+            self.data.push_all(data);
+        }
+
+        fn on_frame_complete(&mut self, _: &FrameParser) {
+            self.complete = true;
+        }
+    }
+
+    #[test]
+    fn test_decodes_a_masked_text_frame() {
+        let data = [0x81u8, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let data = data.as_slice();
+        let mut parser = FrameParser::new();
+        let mut handler = CollectingFrameHandler::new();
+        assert_eq!(parser.parse(data, &mut handler), Ok(data.len()));
+        assert_eq!(handler.fin, Some(true));
+        assert_eq!(handler.opcode, Some(Text));
+        assert_eq!(handler.payload_len, Some(5));
+        assert_eq!(handler.data, "Hello".as_bytes().to_vec());
+        assert!(handler.complete);
+    }
+}
@@ -0,0 +1,380 @@
+//! WebSocket handshake and frame decoding, built on top of the
+//! `Connection: Upgrade` detection `http::parser::Parser` already performs.
+//!
+//! `parser::Parser` stops at the header boundary and reports
+//! `should_upgrade()` once it sees a `Connection: upgrade` request, but it
+//! has no opinion on what happens next. This module covers the two pieces
+//! an HTTP server needs to actually speak WebSocket from there: computing
+//! the `Sec-WebSocket-Accept` token for the `101` response, and decoding
+//! the frames that follow it.
+
+#![experimental]
+
+/// The GUID a server appends to a client's `Sec-WebSocket-Key` before
+/// hashing, fixed by RFC 6455 section 1.3.
+static GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Compute the `Sec-WebSocket-Accept` header value for a client's
+/// `Sec-WebSocket-Key`: `base64(SHA1(key + GUID))`. The result is always a
+/// 28-byte base64 string, the SHA-1 digest being a fixed 20 bytes.
+pub fn accept_key(key: &[u8]) -> String {
+    let mut buf: Vec<u8> = Vec::with_capacity(key.len() + GUID.len());
+    buf.push_all(key);
+    buf.push_all(GUID.as_bytes());
+    base64_encode(sha1(buf.as_slice()).as_slice())
+}
+
+/// A WebSocket frame's opcode (RFC 6455 section 5.2).
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum OpCode {
+    /// Continuation of a fragmented message.
+    Continuation,
+    /// A UTF-8 text payload.
+    Text,
+    /// An opaque binary payload.
+    Binary,
+    /// The connection close handshake.
+    Close,
+    /// A ping control frame.
+    Ping,
+    /// A pong control frame.
+    Pong,
+    /// An opcode RFC 6455 leaves reserved for future use.
+    Reserved(u8),
+}
+
+impl OpCode {
+    fn from_nibble(nibble: u8) -> OpCode {
+        match nibble {
+            0x0 => Continuation,
+            0x1 => Text,
+            0x2 => Binary,
+            0x8 => Close,
+            0x9 => Ping,
+            0xA => Pong,
+            other => Reserved(other),
+        }
+    }
+}
+
+/// Frame parser event handler, analogous to `parser::MessageHandler`.
+pub trait FrameHandler {
+    #[allow(unused_variable)]
+    /// Called once a frame's header (FIN bit, opcode and payload length)
+    /// has been decoded, before any of its payload bytes are dispatched.
+    /// Default implementation is nothing to do.
+    fn on_frame_header(&mut self, parser: &FrameParser, fin: bool, opcode: OpCode, payload_len: uint) {
+    }
+
+    #[allow(unused_variable)]
+    /// Called with each contiguous run of unmasked payload bytes as the
+    /// parser produces them. Default implementation is nothing to do.
+    fn on_frame_data(&mut self, parser: &FrameParser, data: &[u8]) {
+    }
+
+    #[allow(unused_variable)]
+    /// Called once a frame's payload has been fully delivered.
+    /// Default implementation is nothing to do.
+    fn on_frame_complete(&mut self, parser: &FrameParser) {
+    }
+}
+
+/// A list specifying categories of frame decode errors.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum FrameError {
+    /// Any frame error not part of this list.
+    OtherFrameError,
+}
+
+pub type FrameParseResult = Result<uint, FrameError>;
+
+#[deriving(PartialEq, Eq, Clone, Show)]
+enum FrameState {
+    FrameStart,
+    LengthShort,
+    LengthExt16,
+    LengthExt64,
+    MaskingKey,
+    Payload,
+    Dead,
+}
+
+/// Decodes the RFC 6455 frame format from a byte stream. One `FrameParser`
+/// decodes a single frame at a time; call `reset` (or construct a fresh
+/// one) to decode the next.
+pub struct FrameParser {
+    state: FrameState,
+    index: uint,
+
+    fin: bool,
+    opcode: OpCode,
+    masked: bool,
+    length_rest: uint,
+    payload_len: uint,
+
+    mask: [u8, ..4],
+    mask_index: uint,
+}
+
+impl FrameParser {
+    /// Create a new `FrameParser` positioned at the start of a frame.
+    pub fn new() -> FrameParser {
+        FrameParser {
+            state: FrameStart,
+            index: 0,
+            fin: false,
+            opcode: Continuation,
+            masked: false,
+            length_rest: 0,
+            payload_len: 0,
+            mask: [0, 0, 0, 0],
+            mask_index: 0,
+        }
+    }
+
+    /// Reset to decode another frame, e.g. once `on_frame_complete` fires.
+    pub fn reset(&mut self) {
+        self.state = FrameStart;
+        self.index = 0;
+        self.fin = false;
+        self.opcode = Continuation;
+        self.masked = false;
+        self.length_rest = 0;
+        self.payload_len = 0;
+        self.mask_index = 0;
+    }
+
+    /// Whether the just-decoded (or in-progress) frame is the final
+    /// fragment of its message.
+    pub fn fin(&self) -> bool {
+        self.fin
+    }
+
+    /// The just-decoded (or in-progress) frame's opcode.
+    pub fn opcode(&self) -> OpCode {
+        self.opcode.clone()
+    }
+
+    /// The just-decoded (or in-progress) frame's payload length.
+    pub fn payload_len(&self) -> uint {
+        self.payload_len
+    }
+
+    /// Which state to enter once a frame's header is fully decoded: the
+    /// masking key if the frame carries one, straight to `Dead` for an
+    /// unmasked zero-length payload (there are no payload bytes left to
+    /// wait for), or `Payload` otherwise.
+    #[inline]
+    fn next_state_after_header(&self) -> FrameState {
+        if self.masked {
+            MaskingKey
+        } else if self.payload_len == 0 {
+            Dead
+        } else {
+            Payload
+        }
+    }
+
+    /// Feed `data` to the parser, reporting decoded events to `handler`.
+    /// Returns the number of bytes consumed, which may be less than
+    /// `data.len()` once the frame completes.
+    pub fn parse<H: FrameHandler>(&mut self, data: &[u8], handler: &mut H) -> FrameParseResult {
+        let mut read = 0u;
+
+        while read < data.len() && self.state != Dead {
+            match self.state {
+                FrameStart => {
+                    let byte = data[read];
+                    read += 1;
+                    self.fin = byte & 0x80 != 0;
+                    self.opcode = OpCode::from_nibble(byte & 0x0F);
+                    self.state = LengthShort;
+                }
+                LengthShort => {
+                    let byte = data[read];
+                    read += 1;
+                    self.masked = byte & 0x80 != 0;
+                    let len = (byte & 0x7F) as uint;
+                    match len {
+                        126 => { self.length_rest = 0; self.index = 0; self.state = LengthExt16; }
+                        127 => { self.length_rest = 0; self.index = 0; self.state = LengthExt64; }
+                        _   => {
+                            self.payload_len = len;
+                            self.index = 0;
+                            handler.on_frame_header(self, self.fin, self.opcode.clone(), self.payload_len);
+                            self.state = self.next_state_after_header();
+                            if self.state == Dead { handler.on_frame_complete(self) }
+                        }
+                    }
+                }
+                LengthExt16 => {
+                    self.length_rest = (self.length_rest << 8) | data[read] as uint;
+                    read += 1;
+                    self.index += 1;
+                    if self.index == 2 {
+                        self.payload_len = self.length_rest;
+                        self.index = 0;
+                        handler.on_frame_header(self, self.fin, self.opcode.clone(), self.payload_len);
+                        self.state = self.next_state_after_header();
+                        if self.state == Dead { handler.on_frame_complete(self) }
+                    }
+                }
+                LengthExt64 => {
+                    self.length_rest = (self.length_rest << 8) | data[read] as uint;
+                    read += 1;
+                    self.index += 1;
+                    if self.index == 8 {
+                        self.payload_len = self.length_rest;
+                        self.index = 0;
+                        handler.on_frame_header(self, self.fin, self.opcode.clone(), self.payload_len);
+                        self.state = self.next_state_after_header();
+                        if self.state == Dead { handler.on_frame_complete(self) }
+                    }
+                }
+                MaskingKey => {
+                    self.mask[self.index] = data[read];
+                    read += 1;
+                    self.index += 1;
+                    if self.index == 4 {
+                        self.mask_index = 0;
+                        self.index = 0;
+                        if self.payload_len == 0 {
+                            handler.on_frame_complete(self);
+                            self.state = Dead;
+                        } else {
+                            self.state = Payload;
+                        }
+                    }
+                }
+                Payload => {
+                    let rest = self.payload_len - self.index;
+                    let available = data.len() - read;
+                    let take = if available < rest { available } else { rest };
+                    if take == 0 { break }
+
+                    let chunk = data.slice(read, read + take);
+                    if self.masked {
+                        let mut unmasked: Vec<u8> = Vec::with_capacity(take);
+                        for &byte in chunk.iter() {
+                            unmasked.push(byte ^ self.mask[self.mask_index % 4]);
+                            self.mask_index += 1;
+                        }
+                        handler.on_frame_data(self, unmasked.as_slice());
+                    } else {
+                        handler.on_frame_data(self, chunk);
+                    }
+
+                    read += take;
+                    self.index += take;
+                    if self.index == self.payload_len {
+                        handler.on_frame_complete(self);
+                        self.state = Dead;
+                    }
+                }
+                Dead => unreachable!(),
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// A base64 alphabet character for each 6-bit group (RFC 4648 section 4).
+static BASE64_ALPHABET: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `data` as standard (padded) base64.
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    let mut i = 0u;
+    while i + 3 <= data.len() {
+        let n = (data[i] as u32 << 16) | (data[i + 1] as u32 << 8) | data[i + 2] as u32;
+        out.push(BASE64_ALPHABET[(n >> 18 & 0x3F) as uint] as char);
+        out.push(BASE64_ALPHABET[(n >> 12 & 0x3F) as uint] as char);
+        out.push(BASE64_ALPHABET[(n >> 6 & 0x3F) as uint] as char);
+        out.push(BASE64_ALPHABET[(n & 0x3F) as uint] as char);
+        i += 3;
+    }
+    match data.len() - i {
+        1 => {
+            let n = data[i] as u32 << 16;
+            out.push(BASE64_ALPHABET[(n >> 18 & 0x3F) as uint] as char);
+            out.push(BASE64_ALPHABET[(n >> 12 & 0x3F) as uint] as char);
+            out.push_str("==");
+        }
+        2 => {
+            let n = (data[i] as u32 << 16) | (data[i + 1] as u32 << 8);
+            out.push(BASE64_ALPHABET[(n >> 18 & 0x3F) as uint] as char);
+            out.push(BASE64_ALPHABET[(n >> 12 & 0x3F) as uint] as char);
+            out.push(BASE64_ALPHABET[(n >> 6 & 0x3F) as uint] as char);
+            out.push_str("=");
+        }
+        _ => {}
+    }
+    out
+}
+
+/// The SHA-1 initial hash state (FIPS 180-4 section 5.3.1).
+static SHA1_INIT: [u32, ..5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+/// Compute the 20-byte SHA-1 digest of `data` (FIPS 180-4). `bee` only
+/// needs this for the WebSocket handshake token, so it is kept private and
+/// minimal rather than exposed as a general-purpose hashing API.
+fn sha1(data: &[u8]) -> [u8, ..20] {
+    let mut h = SHA1_INIT;
+
+    let bit_len = (data.len() as u64) * 8;
+    let mut msg: Vec<u8> = Vec::with_capacity(data.len() + 72);
+    msg.push_all(data);
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    for i in range(0u, 8) {
+        msg.push((bit_len >> (56 - 8 * i)) as u8);
+    }
+
+    let mut w = [0u32, ..80];
+    for chunk in msg.as_slice().chunks(64) {
+        for i in range(0u, 16) {
+            w[i] = (chunk[i * 4] as u32 << 24) | (chunk[i * 4 + 1] as u32 << 16)
+                 | (chunk[i * 4 + 2] as u32 << 8) | chunk[i * 4 + 3] as u32;
+        }
+        for i in range(16u, 80) {
+            let x = w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16];
+            w[i] = (x << 1) | (x >> 31);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for i in range(0u, 80) {
+            let (f, k) = match i {
+                0..19  => ((b & c) | ((!b) & d), 0x5A827999u32),
+                20..39 => (b ^ c ^ d, 0x6ED9EBA1u32),
+                40..59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDCu32),
+                _      => (b ^ c ^ d, 0xCA62C1D6u32),
+            };
+            let temp = ((a << 5) | (a >> 27))
+                + f + e + k + w[i];
+            e = d;
+            d = c;
+            c = (b << 30) | (b >> 2);
+            b = a;
+            a = temp;
+        }
+
+        h[0] += a;
+        h[1] += b;
+        h[2] += c;
+        h[3] += d;
+        h[4] += e;
+    }
+
+    let mut digest = [0u8, ..20];
+    for i in range(0u, 5) {
+        digest[i * 4]     = (h[i] >> 24) as u8;
+        digest[i * 4 + 1] = (h[i] >> 16) as u8;
+        digest[i * 4 + 2] = (h[i] >> 8) as u8;
+        digest[i * 4 + 3] = h[i] as u8;
+    }
+    digest
+}